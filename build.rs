@@ -0,0 +1,115 @@
+//! Embed build identification into the kernel image for `sys::uname`, and
+//! extract `kexample` doc snippets into ktest cases with the `examples`
+//! feature.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs};
+
+fn main() {
+    embed_version();
+    if env::var_os("CARGO_FEATURE_EXAMPLES").is_some() {
+        extract_examples();
+    }
+}
+
+fn embed_version() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git_hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    let dirty = if dirty { "-dirty" } else { "" };
+    println!("cargo:rustc-env=KOE_GIT_HASH={git_hash}{dirty}");
+
+    // Honour SOURCE_DATE_EPOCH for reproducible builds.
+    let build_time = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs())
+        });
+    println!("cargo:rustc-env=KOE_BUILD_TIME={build_time}");
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_owned))
+        .map(|feature| feature.to_lowercase().replace('_', "-"))
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+    println!(
+        "cargo:rustc-env=KOE_FEATURES={}",
+        features.join(",")
+    );
+}
+
+/// Write every ```` ```kexample ```` block in the doc comments under `src`
+/// into `examples.rs` in `OUT_DIR`, as functions listed in `EXAMPLES`.
+fn extract_examples() {
+    println!("cargo:rerun-if-changed=src");
+
+    let mut files = Vec::new();
+    find_sources(Path::new("src"), &mut files);
+    files.sort();
+
+    let mut fns = String::new();
+    let mut list = String::new();
+    let mut example_cnt = 0;
+    for file in files {
+        let source = fs::read_to_string(&file).expect("source should be readable");
+        let mut body: Option<(usize, String)> = None;
+        for (line_idx, line) in source.lines().enumerate() {
+            let Some(doc) = line.trim_start().strip_prefix("///") else {
+                continue;
+            };
+            let doc = doc.strip_prefix(' ').unwrap_or(doc);
+            match (&mut body, doc.trim_end()) {
+                (None, "```kexample") => body = Some((line_idx + 2, String::new())),
+                (Some((start, code)), "```") => {
+                    let name = format!("example_{}", example_cnt);
+                    let location = format!("{}:{}", file.display(), start);
+                    writeln!(fns, "fn {}() {{\n{}}}\n", name, code).unwrap();
+                    writeln!(list, "    ({:?}, {}),", location, name).unwrap();
+                    example_cnt += 1;
+                    body = None;
+                },
+                (Some((_, code)), "") => code.push('\n'),
+                (Some((_, code)), _) => writeln!(code, "    {}", doc).unwrap(),
+                (None, _) => (),
+            }
+        }
+    }
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("examples.rs");
+    let generated = format!(
+        "{}/// Examples with the location of their first line.\nstatic EXAMPLES: &[(&str, fn())] \
+         = &[\n{}];\n",
+        fns, list
+    );
+    fs::write(out, generated).expect("examples should be writable");
+}
+
+fn find_sources(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).expect("source directory should be readable") {
+        let path = entry.expect("source directory should be readable").path();
+        if path.is_dir() {
+            find_sources(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            files.push(path);
+        }
+    }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+}
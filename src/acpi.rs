@@ -0,0 +1,229 @@
+//! Minimal lookup of ACPI system description tables.
+//!
+//! Only the tables are parsed; there is no AML interpreter. The one AML
+//! object needed, the `\_S5` sleep type for soft-off, is found by a byte
+//! pattern scan of the DSDT.
+
+use core::{ptr, slice};
+
+use multiboot2::BootInformation;
+
+use crate::mem::addr::Addr;
+use crate::mem::PhysicalRemapSpace;
+
+/// Physical address of the root system description table.
+#[derive(Debug, Clone, Copy)]
+enum RootTable {
+    Rsdt(usize),
+    Xsdt(usize),
+}
+
+static ROOT_TABLE: spin::Once<RootTable> = spin::Once::new();
+
+/// Find the root table from the RSDP copied by the bootloader.
+///
+/// Tables can only be looked up after memory is initialized.
+pub fn init(boot_info: &BootInformation) {
+    let xsdt = boot_info
+        .rsdp_v2_tag()
+        .filter(|rsdp| rsdp.checksum_is_valid())
+        .map(|rsdp| RootTable::Xsdt(rsdp.xsdt_address()));
+    let rsdt = boot_info
+        .rsdp_v1_tag()
+        .filter(|rsdp| rsdp.checksum_is_valid())
+        .map(|rsdp| RootTable::Rsdt(rsdp.rsdt_address()));
+    let Some(root) = xsdt.or(rsdt) else {
+        return;
+    };
+    ROOT_TABLE.call_once(|| root);
+}
+
+/// Header common to all system description tables.
+#[repr(C, packed)]
+#[derive(Debug)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+impl SdtHeader {
+    /// Returns the whole table, including the header.
+    pub fn bytes(&self) -> &[u8] {
+        // SAFETY: A table spans `length` bytes from its header.
+        unsafe {
+            slice::from_raw_parts(
+                ptr::from_ref(self).cast(),
+                self.length as usize,
+            )
+        }
+    }
+
+    fn is_valid(&self) -> bool { self.bytes().iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == 0 }
+}
+
+/// An ACPI generic address structure.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+impl GenericAddress {
+    pub const SPACE_MEMORY: u8 = 0;
+    pub const SPACE_IO: u8 = 1;
+    pub const SPACE_PCI_CONFIG: u8 = 2;
+}
+
+/// Find the first valid table with `signature`.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    let (root, entry_size) = match *ROOT_TABLE.get()? {
+        RootTable::Rsdt(addr) => (addr, size_of::<u32>()),
+        RootTable::Xsdt(addr) => (addr, size_of::<u64>()),
+    };
+    let root = table_at(root)?;
+
+    let entries = &root.bytes()[size_of::<SdtHeader>()..];
+    entries
+        .chunks_exact(entry_size)
+        .map(|entry| {
+            let mut addr = [0; 8];
+            addr[..entry_size].copy_from_slice(entry);
+            usize::from_le_bytes(addr)
+        })
+        .filter_map(table_at)
+        .find(|table| &table.signature == signature)
+}
+
+/// Returns the reset register and the value to write to it, if the platform
+/// supports reset through the FADT.
+pub fn reset_register() -> Option<(GenericAddress, u8)> {
+    const FLAGS_OFFSET: usize = 112;
+    const RESET_REG_OFFSET: usize = 116;
+    const RESET_VALUE_OFFSET: usize = 128;
+    const FLAGS_RESET_REG_SUP: u32 = 1 << 10;
+
+    let fadt = find_table(b"FACP")?.bytes();
+    if fadt.len() <= RESET_VALUE_OFFSET {
+        return None;
+    }
+
+    let flags = u32::from_le_bytes(fadt[FLAGS_OFFSET..FLAGS_OFFSET + 4].try_into().ok()?);
+    if flags & FLAGS_RESET_REG_SUP == 0 {
+        return None;
+    }
+    // SAFETY: The reset register lies within the FADT.
+    let reg =
+        unsafe { ptr::read_unaligned(fadt[RESET_REG_OFFSET..].as_ptr().cast::<GenericAddress>()) };
+    Some((reg, fadt[RESET_VALUE_OFFSET]))
+}
+
+/// Registers and values to enter the S5 soft-off state.
+#[derive(Debug, Clone, Copy)]
+pub struct SoftOff {
+    pub pm1a_cnt: u16,
+    /// `None` if the platform has no PM1b control block.
+    pub pm1b_cnt: Option<u16>,
+    pub slp_typ_a: u8,
+    pub slp_typ_b: u8,
+    /// SMI command port and value switching the platform into ACPI mode.
+    pub acpi_enable: Option<(u16, u8)>,
+}
+
+/// Returns how to enter S5, if the FADT and DSDT describe it.
+pub fn soft_off() -> Option<SoftOff> {
+    const DSDT_OFFSET: usize = 40;
+    const SMI_CMD_OFFSET: usize = 48;
+    const ACPI_ENABLE_OFFSET: usize = 52;
+    const PM1A_CNT_OFFSET: usize = 64;
+    const PM1B_CNT_OFFSET: usize = 68;
+    const X_DSDT_OFFSET: usize = 140;
+
+    let fadt = find_table(b"FACP")?.bytes();
+    let read_u32 = |offset: usize| {
+        let bytes = fadt.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(
+            bytes.try_into().ok()?,
+        ))
+    };
+    let x_dsdt = fadt
+        .get(X_DSDT_OFFSET..X_DSDT_OFFSET + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .filter(|&addr| addr != 0);
+    let dsdt = match x_dsdt {
+        Some(addr) => addr as usize,
+        None => read_u32(DSDT_OFFSET)? as usize,
+    };
+    let (slp_typ_a, slp_typ_b) = find_s5(table_at(dsdt)?.bytes())?;
+
+    let pm1a_cnt = read_u32(PM1A_CNT_OFFSET).filter(|&port| port != 0)?;
+    let pm1b_cnt = read_u32(PM1B_CNT_OFFSET).filter(|&port| port != 0);
+    let smi_cmd = read_u32(SMI_CMD_OFFSET).unwrap_or(0);
+    let acpi_enable = fadt.get(ACPI_ENABLE_OFFSET).copied().unwrap_or(0);
+    Some(SoftOff {
+        pm1a_cnt: pm1a_cnt as u16,
+        pm1b_cnt: pm1b_cnt.map(|port| port as u16),
+        slp_typ_a,
+        slp_typ_b,
+        acpi_enable: (smi_cmd != 0 && acpi_enable != 0).then_some((smi_cmd as u16, acpi_enable)),
+    })
+}
+
+/// Find `Name (_S5, Package () { SLP_TYPa, SLP_TYPb, .. })` in `aml`.
+fn find_s5(aml: &[u8]) -> Option<(u8, u8)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const ROOT_CHAR: u8 = b'\\';
+
+    let name_pos = (1..aml.len().saturating_sub(3)).find(|&pos| {
+        &aml[pos..pos + 4] == b"_S5_"
+            && (aml[pos - 1] == NAME_OP
+                || pos >= 2 && aml[pos - 1] == ROOT_CHAR && aml[pos - 2] == NAME_OP)
+    })?;
+
+    let mut rest = aml.get(name_pos + 4..)?;
+    if *rest.first()? != PACKAGE_OP {
+        return None;
+    }
+    // Skip the package length, whose lead byte gives the count of following
+    // bytes in bits 7-6, then the element count.
+    let pkg_len_len = 1 + (*rest.get(1)? >> 6) as usize;
+    rest = rest.get(1 + pkg_len_len + 1..)?;
+
+    let (slp_typ_a, len) = parse_aml_integer(rest)?;
+    let (slp_typ_b, _) = parse_aml_integer(rest.get(len..)?)?;
+    Some((slp_typ_a, slp_typ_b))
+}
+
+/// Parse a small AML integer, returning its value and encoded length.
+fn parse_aml_integer(aml: &[u8]) -> Option<(u8, usize)> {
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+    const BYTE_PREFIX: u8 = 0x0A;
+
+    match *aml.first()? {
+        ZERO_OP => Some((0, 1)),
+        ONE_OP => Some((1, 1)),
+        BYTE_PREFIX => Some((*aml.get(1)?, 2)),
+        _ => None,
+    }
+}
+
+fn table_at(paddr: usize) -> Option<&'static SdtHeader> {
+    if paddr == 0 {
+        return None;
+    }
+    let vaddr = PhysicalRemapSpace::p2v(Addr::new(paddr));
+    // SAFETY: ACPI tables are reserved by the firmware and always mapped in
+    // the physical remap space.
+    let table = unsafe { &*(vaddr.usize() as *const SdtHeader) };
+    table.is_valid().then_some(table)
+}
@@ -1,5 +1,41 @@
 use core::arch::global_asm;
+use core::ops::Range;
 
+use multiboot2::BootInformation;
+
+use crate::acpi;
+use crate::phase::{self, Phase};
+
+pub mod cmdline;
+#[cfg(feature = "graphics-console")]
+pub mod framebuffer;
+pub mod module;
 mod multiboot2_header;
 
+#[cfg(feature = "graphics-console")]
+pub use framebuffer::framebuffer;
+pub use module::modules;
+
 global_asm!(include_str!("boot/boot.S"));
+
+/// Copy out everything needed from the boot information.
+///
+/// This should be called before memory initialization, which unmaps the boot
+/// information.
+pub fn init(boot_info: &BootInformation) {
+    phase::enter(Phase::Boot);
+    cmdline::init(boot_info);
+    module::init(boot_info);
+    #[cfg(feature = "graphics-console")]
+    framebuffer::init(boot_info);
+    acpi::init(boot_info);
+}
+
+/// Returns the range of the stack set up by the bootstrap code.
+pub fn boot_stack() -> Range<usize> {
+    unsafe extern "C" {
+        static stack_bottom: u8;
+        static stack_top: u8;
+    }
+    (&raw const stack_bottom as usize)..(&raw const stack_top as usize)
+}
@@ -0,0 +1,61 @@
+//! Kernel command line passed by the bootloader.
+//!
+//! The command line is a list of whitespace separated options, each being
+//! either a flag `key` or a pair `key=value`.
+
+use arrayvec::ArrayString;
+use multiboot2::BootInformation;
+
+const CMDLINE_LEN: usize = 256;
+
+static CMDLINE: spin::Once<ArrayString<CMDLINE_LEN>> = spin::Once::new();
+
+/// Copy the command line out of the boot information.
+///
+/// This should be called before the boot information is unmapped. Command
+/// lines longer than `CMDLINE_LEN` are truncated at an option boundary.
+pub fn init(boot_info: &BootInformation) {
+    CMDLINE.call_once(|| {
+        let mut cmdline = ArrayString::new();
+        let raw = boot_info
+            .command_line_tag()
+            .and_then(|tag| tag.cmdline().ok())
+            .unwrap_or("");
+        for opt in raw.split_whitespace() {
+            let sep = if cmdline.is_empty() {
+                ""
+            } else {
+                " "
+            };
+            if cmdline.remaining_capacity() < sep.len() + opt.len() {
+                break;
+            }
+            cmdline.push_str(sep);
+            cmdline.push_str(opt);
+        }
+        cmdline
+    });
+}
+
+/// Returns the whole command line. Empty if [`init`] has not been called.
+pub fn raw() -> &'static str { CMDLINE.get().map_or("", |cmdline| cmdline.as_str()) }
+
+/// Returns the value of option `key`. A flag option has an empty value.
+///
+/// If `key` is given multiple times, the last one wins.
+pub fn get(key: &str) -> Option<&'static str> {
+    raw()
+        .split_whitespace()
+        .filter_map(|opt| match opt.split_once('=') {
+            Some((k, v)) => (k == key).then_some(v),
+            None => (opt == key).then_some(""),
+        })
+        .last()
+}
+
+/// Returns the value of option `key` parsed as `T`. `None` if the option is
+/// missing or malformed.
+pub fn parse<T: core::str::FromStr>(key: &str) -> Option<T> { get(key)?.parse().ok() }
+
+/// Check if option `key` is given.
+pub fn has(key: &str) -> bool { get(key).is_some() }
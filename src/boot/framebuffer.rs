@@ -0,0 +1,75 @@
+//! Framebuffer set up by the bootloader.
+//!
+//! The framebuffer memory is reserved from the boot memory allocator, so
+//! graphics drivers can map it without re-parsing the boot information.
+
+pub use multiboot2::FramebufferField;
+use multiboot2::{BootInformation, FramebufferType};
+
+use crate::mem::addr::{Addr, AddrRange};
+use crate::mem::UMASpace;
+
+static FRAMEBUFFER: spin::Once<Framebuffer> = spin::Once::new();
+
+/// Pixel format of a framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramebufferFormat {
+    /// Indexed color. The palette is not kept.
+    Indexed,
+    /// Direct color with the position and size of each channel in a pixel.
+    Rgb {
+        red: FramebufferField,
+        green: FramebufferField,
+        blue: FramebufferField,
+    },
+    /// EGA text, with the width and height in characters.
+    Text,
+}
+
+/// Geometry of the boot framebuffer.
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    range: AddrRange<UMASpace>,
+    /// Bytes per line.
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel.
+    pub bpp: u8,
+    pub format: FramebufferFormat,
+}
+impl Framebuffer {
+    /// Returns the physical memory range holding the framebuffer.
+    pub fn range(&self) -> AddrRange<UMASpace> { self.range }
+}
+
+/// Record the framebuffer passed in the boot information, if any.
+///
+/// This should be called before the boot information is unmapped.
+pub fn init(boot_info: &BootInformation) {
+    let Some(Ok(tag)) = boot_info.framebuffer_tag() else {
+        return;
+    };
+    let format = match tag.buffer_type() {
+        Ok(FramebufferType::Indexed { .. }) => FramebufferFormat::Indexed,
+        Ok(FramebufferType::RGB { red, green, blue }) =>
+            FramebufferFormat::Rgb { red, green, blue },
+        Ok(FramebufferType::Text) => FramebufferFormat::Text,
+        Err(_) => return,
+    };
+
+    let base = Addr::new(tag.address() as usize);
+    let size = tag.pitch() as usize * tag.height() as usize;
+    FRAMEBUFFER.call_once(|| Framebuffer {
+        range: AddrRange::new(base, size),
+        pitch: tag.pitch(),
+        width: tag.width(),
+        height: tag.height(),
+        bpp: tag.bpp(),
+        format,
+    });
+}
+
+/// Returns the boot framebuffer. `None` if the bootloader did not set one
+/// up or [`init`] has not been called.
+pub fn framebuffer() -> Option<&'static Framebuffer> { FRAMEBUFFER.get() }
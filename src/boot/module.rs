@@ -0,0 +1,77 @@
+//! Boot modules loaded by the bootloader.
+//!
+//! A module is identified by the first word of its command line, e.g.
+//! `module2 /boot/initrd.tar initrd` registers a module named `initrd`.
+//! Module memory is reserved from the boot memory allocator, and stays
+//! reserved for the lifetime of the kernel.
+
+use arrayvec::{ArrayString, ArrayVec};
+use multiboot2::BootInformation;
+
+use crate::mem::addr::{Addr, AddrRange};
+use crate::mem::{PhysicalRemapSpace, UMASpace};
+
+const MODULES_LEN: usize = 16;
+const MODULE_CMDLINE_LEN: usize = 64;
+
+static MODULES: spin::Once<ArrayVec<BootModule, MODULES_LEN>> = spin::Once::new();
+
+/// A module loaded by the bootloader.
+#[derive(Debug)]
+pub struct BootModule {
+    cmdline: ArrayString<MODULE_CMDLINE_LEN>,
+    range: AddrRange<UMASpace>,
+}
+impl BootModule {
+    /// Returns the name of the module, which is the first word of its command
+    /// line.
+    pub fn name(&self) -> &str { self.cmdline.split_whitespace().next().unwrap_or("") }
+
+    /// Returns the command line of the module, including its name.
+    pub fn cmdline(&self) -> &str { &self.cmdline }
+
+    /// Returns the physical memory range holding the module.
+    pub fn range(&self) -> AddrRange<UMASpace> { self.range }
+
+    /// Returns the module content.
+    ///
+    /// This should not be called before memory is initialized.
+    pub fn bytes(&self) -> &'static [u8] {
+        let base = PhysicalRemapSpace::p2v(self.range.base);
+        // SAFETY: Module memory is reserved, and is mapped in
+        // PhysicalRemapSpace.
+        unsafe { core::slice::from_raw_parts(base.into_ptr(), self.range.size) }
+    }
+}
+
+/// Record the modules passed in the boot information.
+///
+/// This should be called before the boot information is unmapped. Modules
+/// beyond `MODULES_LEN` are ignored, and command lines longer than
+/// `MODULE_CMDLINE_LEN` are truncated.
+pub fn init(boot_info: &BootInformation) {
+    MODULES.call_once(|| {
+        let mut modules = ArrayVec::new();
+        for tag in boot_info.module_tags().take(MODULES_LEN) {
+            let mut cmdline = ArrayString::new();
+            for c in tag.cmdline().unwrap_or("").chars() {
+                if cmdline.try_push(c).is_err() {
+                    break;
+                }
+            }
+
+            let base = Addr::new(tag.start_address() as usize);
+            let range = AddrRange::new(base, tag.module_size() as usize);
+            modules.push(BootModule { cmdline, range });
+        }
+        modules
+    });
+}
+
+/// Returns all boot modules. Empty if [`init`] has not been called.
+pub fn modules() -> &'static [BootModule] { MODULES.get().map_or(&[], |modules| modules) }
+
+/// Returns the boot module named `name`.
+pub fn find(name: &str) -> Option<&'static BootModule> {
+    modules().iter().find(|module| module.name() == name)
+}
@@ -18,7 +18,10 @@ pub fn hlt() -> ! {
 
 pub mod array_forest;
 pub mod ll;
+pub mod lock;
+pub mod mmio;
 pub mod panic;
+pub mod stack;
 
 #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 pub mod pmio;
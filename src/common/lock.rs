@@ -0,0 +1,174 @@
+//! Spin lock with optional contention profiling.
+//!
+//! With the `lockstat` feature, a [`Mutex`] records how often and for how
+//! many TSC cycles it was waited on, and where its holder acquired it when it
+//! was contended. Locks [`register`]ed by their subsystems are reported
+//! through [`for_each`]. Without the feature, [`Mutex`] is a plain
+//! [`spin::Mutex`].
+
+#[cfg(feature = "lockstat")]
+use core::arch::x86_64::_rdtsc;
+#[cfg(feature = "lockstat")]
+use core::panic::Location;
+#[cfg(feature = "lockstat")]
+use core::ptr;
+#[cfg(feature = "lockstat")]
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+#[cfg(feature = "lockstat")]
+use arrayvec::ArrayVec;
+pub use spin::MutexGuard;
+
+#[cfg(feature = "lockstat")]
+use crate::interrupt::InterruptGuard;
+
+#[cfg(feature = "lockstat")]
+const LOCKS_LEN: usize = 32;
+
+#[cfg(feature = "lockstat")]
+static LOCKS: spin::Mutex<ArrayVec<&'static LockStat, LOCKS_LEN>> =
+    spin::Mutex::new(ArrayVec::new_const());
+
+/// A named spin lock.
+pub struct Mutex<T: ?Sized> {
+    #[cfg(feature = "lockstat")]
+    stat: LockStat,
+    inner: spin::Mutex<T>,
+}
+impl<T> Mutex<T> {
+    #[allow(unused_variables)]
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Self {
+            #[cfg(feature = "lockstat")]
+            stat: LockStat::new(name),
+            inner: spin::Mutex::new(value),
+        }
+    }
+}
+impl<T: ?Sized> Mutex<T> {
+    /// Spin until the lock is acquired.
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        #[cfg(feature = "lockstat")]
+        {
+            let site = Location::caller();
+            if let Some(guard) = self.inner.try_lock() {
+                self.stat.acquired(site);
+                return guard;
+            }
+
+            let holder = self.stat.holder.load(Ordering::Relaxed);
+            // SAFETY: TSC is supported on all x86-64 CPUs.
+            let start = unsafe { _rdtsc() };
+            let guard = self.inner.lock();
+            let wait = unsafe { _rdtsc() } - start;
+            self.stat.contended(wait, holder);
+            self.stat.acquired(site);
+            guard
+        }
+        #[cfg(not(feature = "lockstat"))]
+        self.inner.lock()
+    }
+
+    /// Try to acquire the lock without spinning.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+        #[cfg(feature = "lockstat")]
+        self.stat.acquired(Location::caller());
+        Some(guard)
+    }
+
+    /// Force unlock the lock.
+    ///
+    /// # Safety
+    /// See [`spin::Mutex::force_unlock`].
+    pub unsafe fn force_unlock(&self) { unsafe { self.inner.force_unlock() } }
+}
+
+/// Contention record of a [`Mutex`].
+#[cfg(feature = "lockstat")]
+pub struct LockStat {
+    name: &'static str,
+    acquire_cnt: AtomicU64,
+    contend_cnt: AtomicU64,
+    wait_cycles: AtomicU64,
+    max_wait_cycles: AtomicU64,
+    /// Acquisition site of the current holder.
+    holder: AtomicPtr<Location<'static>>,
+    /// Acquisition site of the holder last waited on.
+    contended_holder: AtomicPtr<Location<'static>>,
+}
+#[cfg(feature = "lockstat")]
+impl LockStat {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            acquire_cnt: AtomicU64::new(0),
+            contend_cnt: AtomicU64::new(0),
+            wait_cycles: AtomicU64::new(0),
+            max_wait_cycles: AtomicU64::new(0),
+            holder: AtomicPtr::new(ptr::null_mut()),
+            contended_holder: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn acquired(&self, site: &'static Location<'static>) {
+        self.acquire_cnt.fetch_add(1, Ordering::Relaxed);
+        self.holder.store(
+            ptr::from_ref(site).cast_mut(),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn contended(&self, wait: u64, holder: *mut Location<'static>) {
+        self.contend_cnt.fetch_add(1, Ordering::Relaxed);
+        self.wait_cycles.fetch_add(wait, Ordering::Relaxed);
+        self.max_wait_cycles.fetch_max(wait, Ordering::Relaxed);
+        self.contended_holder.store(holder, Ordering::Relaxed);
+    }
+
+    pub fn name(&self) -> &'static str { self.name }
+
+    pub fn acquire_cnt(&self) -> u64 { self.acquire_cnt.load(Ordering::Relaxed) }
+
+    pub fn contend_cnt(&self) -> u64 { self.contend_cnt.load(Ordering::Relaxed) }
+
+    /// Returns the total TSC cycles spent waiting on the lock.
+    pub fn wait_cycles(&self) -> u64 { self.wait_cycles.load(Ordering::Relaxed) }
+
+    /// Returns the longest wait on the lock in TSC cycles.
+    pub fn max_wait_cycles(&self) -> u64 { self.max_wait_cycles.load(Ordering::Relaxed) }
+
+    /// Returns where the holder last waited on acquired the lock.
+    pub fn contended_holder(&self) -> Option<&'static Location<'static>> {
+        let holder = self.contended_holder.load(Ordering::Relaxed);
+        // SAFETY: holder is either null or from Location::caller.
+        unsafe { holder.as_ref() }
+    }
+}
+
+/// Register `lock` so that it is reported through [`for_each`].
+///
+/// # Panics
+/// Panics if the registry is full.
+#[allow(unused_variables)]
+pub fn register<T: ?Sized>(lock: &'static Mutex<T>) {
+    #[cfg(feature = "lockstat")]
+    {
+        let _guard = InterruptGuard::new();
+        LOCKS
+            .lock()
+            .try_push(&lock.stat)
+            .expect("lock registry should not be full");
+    }
+}
+
+/// Call `f` on every registered lock in registration order.
+#[cfg(feature = "lockstat")]
+pub fn for_each(mut f: impl FnMut(&LockStat)) {
+    let _guard = InterruptGuard::new();
+    for lock in LOCKS.lock().iter() {
+        f(lock);
+    }
+}
@@ -0,0 +1,145 @@
+//! Memory-mapped I/O accessors.
+//!
+//! Every access is volatile and fenced against the compiler, so memory
+//! accesses around a register access are never reordered across it. MMIO
+//! regions are expected to be mapped uncacheable, which makes register
+//! accesses strongly ordered on x86. The explicit CPU barriers are only
+//! needed when ordering register accesses against write-combining or
+//! cacheable memory, e.g. DMA buffers.
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Read a register at `addr`.
+///
+/// # Safety
+/// `addr` should point to a mapped register of type `T`.
+#[inline(always)]
+pub unsafe fn read<T: Copy>(addr: *const T) -> T {
+    // SAFETY: Guaranteed by caller.
+    let value = unsafe { ptr::read_volatile(addr) };
+    compiler_fence(Ordering::SeqCst);
+    value
+}
+
+/// Write `value` to a register at `addr`.
+///
+/// # Safety
+/// `addr` should point to a mapped register of type `T`.
+#[inline(always)]
+pub unsafe fn write<T: Copy>(addr: *mut T, value: T) {
+    compiler_fence(Ordering::SeqCst);
+    // SAFETY: Guaranteed by caller.
+    unsafe { ptr::write_volatile(addr, value) };
+}
+
+/// Full memory barrier. Orders all prior loads and stores before all later
+/// loads and stores.
+#[inline(always)]
+pub fn mb() {
+    unsafe {
+        asm!(
+            "mfence",
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+/// Read memory barrier. Orders all prior loads before all later loads.
+#[inline(always)]
+pub fn rmb() {
+    unsafe {
+        asm!(
+            "lfence",
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+/// Write memory barrier. Orders all prior stores before all later stores,
+/// including write-combining stores.
+#[inline(always)]
+pub fn wmb() {
+    unsafe {
+        asm!(
+            "sfence",
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+pub trait Readable {}
+pub trait Writable {}
+
+/// Access marker of a read-only register.
+pub enum ReadOnly {}
+/// Access marker of a write-only register.
+pub enum WriteOnly {}
+/// Access marker of a read-write register.
+pub enum ReadWrite {}
+
+impl Readable for ReadOnly {}
+impl Writable for WriteOnly {}
+impl Readable for ReadWrite {}
+impl Writable for ReadWrite {}
+
+/// A register of type `T` in a register block.
+///
+/// Register blocks are `#[repr(C)]` structs of `Reg`s and padding which are
+/// accessed through [`RegBlock`]. Accessing a register through `&Reg` is
+/// always volatile.
+#[repr(transparent)]
+pub struct Reg<T: Copy, A = ReadWrite> {
+    value: UnsafeCell<T>,
+    _access: PhantomData<A>,
+}
+impl<T: Copy, A: Readable> Reg<T, A> {
+    #[inline(always)]
+    pub fn read(&self) -> T {
+        // SAFETY: Register blocks are only created from mapped registers.
+        unsafe { read(self.value.get()) }
+    }
+}
+impl<T: Copy, A: Writable> Reg<T, A> {
+    #[inline(always)]
+    pub fn write(&self, value: T) {
+        // SAFETY: Register blocks are only created from mapped registers.
+        unsafe { write(self.value.get(), value) }
+    }
+}
+impl<T: Copy, A: Readable + Writable> Reg<T, A> {
+    /// Read the register, and write back the value returned by `f`.
+    #[inline(always)]
+    pub fn modify(&self, f: impl FnOnce(T) -> T) { self.write(f(self.read())) }
+}
+
+/// A reference to a mapped register block `B`.
+pub struct RegBlock<B> {
+    base: *const B,
+}
+// SAFETY: Registers are accessed with volatile operations. Synchronizing
+// register accesses across CPUs is the driver's responsibility.
+unsafe impl<B> Send for RegBlock<B> {}
+unsafe impl<B> Sync for RegBlock<B> {}
+impl<B> RegBlock<B> {
+    /// Creates a reference to the register block at `base`.
+    ///
+    /// # Safety
+    /// `base` should point to a register block of layout `B`, which stays
+    /// mapped uncacheable for the lifetime of the `RegBlock`.
+    pub const unsafe fn new(base: *const B) -> Self { Self { base } }
+
+    /// Returns the base address of the register block.
+    pub const fn base(&self) -> *const B { self.base }
+}
+impl<B> core::ops::Deref for RegBlock<B> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        // SAFETY: Guaranteed by RegBlock::new
+        unsafe { &*self.base }
+    }
+}
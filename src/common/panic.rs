@@ -1,12 +1,27 @@
 use core::fmt::Write as _;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
 
+use crate::boot::cmdline;
 use crate::common::hlt;
-use crate::drivers;
+use crate::drivers::serial::RawSerial;
+use crate::flight::{self, EventKind};
+use crate::interrupt::{self, InterruptGuard};
+use crate::{drivers, power, time};
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     use drivers::vga::*;
+
+    flight::record(EventKind::Panic, 0);
+    // The console lock may be held by the interrupted code or by the first
+    // panic, so only the lock-free serial path is safe.
+    if interrupt::in_interrupt() || PANICKING.swap(true, Ordering::Relaxed) {
+        raw_panic(info)
+    }
+
     let mut vga_buffer = VGA_BUFFER.lock();
     vga_buffer.clear();
     vga_buffer.set_color(Color::Red, Color::Black, true);
@@ -19,6 +34,35 @@ fn panic(info: &PanicInfo) -> ! {
     ) {
         // I hope linter is happy >:(
     }
+    vga_buffer.flush();
+
+    // Reboot after `panic=` seconds, or halt if unset or 0.
+    let timeout = cmdline::parse::<u64>("panic").unwrap_or(0);
+    if timeout == 0 {
+        drop(vga_buffer);
+        hlt()
+    }
+    write!(
+        *vga_buffer,
+        "\nrebooting in {} seconds",
+        timeout
+    )
+    .ok();
+    vga_buffer.flush();
     drop(vga_buffer);
+    time::delay_ms(timeout * 1000);
+    power::reboot()
+}
+
+/// Report a panic on the serial port without taking any lock, then halt.
+fn raw_panic(info: &PanicInfo) -> ! {
+    core::mem::forget(InterruptGuard::new());
+    write!(
+        RawSerial,
+        "\nKERNEL PANIC: {} at \n{:?}\n",
+        info.message(),
+        info.location(),
+    )
+    .ok();
     hlt()
 }
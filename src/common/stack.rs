@@ -0,0 +1,59 @@
+//! Stack usage watermarking.
+//!
+//! A stack is painted with a pattern before it is used. The deepest point it
+//! has reached is then the lowest word no longer holding the pattern.
+
+use core::arch::asm;
+use core::ops::Range;
+use core::ptr;
+
+const PAINT: u64 = 0x57AC_57AC_57AC_57AC;
+
+/// Paint `stack` with the watermark pattern.
+///
+/// # Safety
+/// `stack` should be a valid, 8-byte aligned stack which is not in use.
+pub unsafe fn paint(stack: Range<usize>) {
+    let mut word = stack.start;
+    while word + size_of::<u64>() <= stack.end {
+        // SAFETY: Guaranteed by caller.
+        unsafe { ptr::write_volatile(word as *mut u64, PAINT) };
+        word += size_of::<u64>();
+    }
+}
+
+/// Paint the part of the current stack below the stack pointer, where
+/// `bottom` is the lowest address of the current stack.
+///
+/// # Safety
+/// `bottom` should be the 8-byte aligned bottom of the current stack.
+pub unsafe fn paint_current(bottom: usize) {
+    // The fill is done without touching the stack, so the whole region below
+    // the stack pointer is free to paint.
+    unsafe {
+        asm!(
+            "mov rcx, rsp",
+            "sub rcx, rdi",
+            "shr rcx, 3",
+            "rep stosq",
+            inout("rdi") bottom => _,
+            in("rax") PAINT,
+            out("rcx") _,
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+/// Returns the deepest usage of painted `stack` in bytes.
+///
+/// # Safety
+/// `stack` should be a valid stack painted by [`paint`] or
+/// [`paint_current`].
+pub unsafe fn max_depth(stack: Range<usize>) -> usize {
+    let mut word = stack.start;
+    // SAFETY: Guaranteed by caller.
+    while word < stack.end && unsafe { ptr::read_volatile(word as *const u64) } == PAINT {
+        word += size_of::<u64>();
+    }
+    stack.end - word
+}
@@ -0,0 +1,48 @@
+//! Processor identification.
+
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+pub mod features;
+pub mod topology;
+
+/// Maximum number of CPUs supported by the kernel.
+pub const MAX_CPUS: usize = 64;
+
+const CPUID_X2APIC_TOPOLOGY: u32 = 0xB;
+
+/// Identifier of a CPU, which is its dense index in [`topology::cpus`] with
+/// the boot CPU at 0. Per-CPU state is indexed by it.
+pub type CpuId = usize;
+
+/// Returns the id of the current CPU.
+///
+/// Before [`topology::init`], only the boot CPU runs and 0 is returned.
+pub fn current_id() -> CpuId {
+    let cpus = topology::cpus();
+    if cpus.is_empty() {
+        return 0;
+    }
+    let apic_id = apic_id();
+    cpus.iter()
+        .position(|cpu| cpu.apic_id == apic_id)
+        .expect("current cpu should be enumerated")
+}
+
+/// Returns the local APIC id of the current CPU.
+///
+/// This is the x2APIC id if CPUID reports it, since the MADT may describe CPUs
+/// by x2APIC ids not fitting in the 8-bit initial APIC id.
+fn apic_id() -> u32 {
+    // SAFETY: CPUID is checked to be supported during boot.
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf >= CPUID_X2APIC_TOPOLOGY {
+        // SAFETY: Leaf is checked to be supported above.
+        let leaf = unsafe { __cpuid_count(CPUID_X2APIC_TOPOLOGY, 0) };
+        if leaf.ebx != 0 {
+            return leaf.edx;
+        }
+    }
+    // SAFETY: CPUID is checked to be supported during boot.
+    let leaf = unsafe { __cpuid(1) };
+    leaf.ebx >> 24
+}
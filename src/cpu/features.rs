@@ -0,0 +1,160 @@
+//! CPU security features.
+//!
+//! Supported features are enabled during boot. Each can be disabled for
+//! debugging with a command line flag, e.g. `nosmap`.
+
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::boot::cmdline;
+use crate::drivers::vga::VGA_BUFFER;
+use crate::log;
+
+const CR4_UMIP: u64 = 1 << 11;
+const CR4_SMEP: u64 = 1 << 20;
+const CR4_SMAP: u64 = 1 << 21;
+
+const MSR_EFER: u32 = 0xC000_0080;
+const EFER_NXE: u64 = 1 << 11;
+
+/// Enabled features, as a bitmap indexed by [`Feature`].
+static ENABLED: AtomicU8 = AtomicU8::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Feature {
+    /// Supervisor mode execution prevention.
+    Smep,
+    /// Supervisor mode access prevention.
+    Smap,
+    /// User mode instruction prevention.
+    Umip,
+    /// No-execute page protection.
+    Nx,
+}
+impl Feature {
+    pub const ALL: [Self; 4] = [Self::Smep, Self::Smap, Self::Umip, Self::Nx];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Smep => "smep",
+            Self::Smap => "smap",
+            Self::Umip => "umip",
+            Self::Nx => "nx",
+        }
+    }
+
+    /// Command line flag disabling the feature.
+    fn disable_flag(self) -> &'static str {
+        match self {
+            Self::Smep => "nosmep",
+            Self::Smap => "nosmap",
+            Self::Umip => "noumip",
+            Self::Nx => "nonx",
+        }
+    }
+
+    fn is_supported(self) -> bool {
+        // SAFETY: CPUID is checked to be supported during boot.
+        let (leaf7, ext_leaf1) = unsafe {
+            (
+                __cpuid_count(7, 0),
+                __cpuid(0x8000_0001),
+            )
+        };
+        match self {
+            Self::Smep => leaf7.ebx & (1 << 7) != 0,
+            Self::Smap => leaf7.ebx & (1 << 20) != 0,
+            Self::Umip => leaf7.ecx & (1 << 2) != 0,
+            Self::Nx => ext_leaf1.edx & (1 << 20) != 0,
+        }
+    }
+
+    /// # Safety
+    /// The feature should be supported, and the kernel should be prepared
+    /// for it.
+    unsafe fn enable(self) {
+        // SAFETY: Guaranteed by caller.
+        unsafe {
+            match self {
+                Self::Smep => set_cr4(CR4_SMEP),
+                Self::Smap => set_cr4(CR4_SMAP),
+                Self::Umip => set_cr4(CR4_UMIP),
+                Self::Nx => wrmsr(MSR_EFER, rdmsr(MSR_EFER) | EFER_NXE),
+            }
+        }
+    }
+}
+
+/// Enable every supported feature not disabled on the command line, and log
+/// their status.
+///
+/// This should be called after the command line is initialized.
+pub fn init() {
+    log!("cpu features:");
+    for feature in Feature::ALL {
+        let status = if !feature.is_supported() {
+            "unsupported"
+        } else if cmdline::has(feature.disable_flag()) {
+            "off"
+        } else {
+            // SAFETY: The feature is supported, and the kernel accesses user
+            // memory only through `mem::user`, which honours SMAP.
+            unsafe { feature.enable() };
+            ENABLED.fetch_or(1 << feature as u8, Ordering::Relaxed);
+            "on"
+        };
+        log!(" {}={}", feature.name(), status);
+    }
+    log!("\n");
+}
+
+pub fn is_enabled(feature: Feature) -> bool {
+    ENABLED.load(Ordering::Relaxed) & (1 << feature as u8) != 0
+}
+
+unsafe fn set_cr4(bits: u64) {
+    // SAFETY: Guaranteed by caller.
+    unsafe {
+        asm!(
+            "mov {tmp}, cr4",
+            "or {tmp}, {bits}",
+            "mov cr4, {tmp}",
+            bits = in(reg) bits,
+            tmp = out(reg) _,
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    // SAFETY: Only architectural MSRs are read.
+    unsafe {
+        asm!(
+            "rdmsr",
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+    (high as u64) << 32 | low as u64
+}
+
+/// # Safety
+/// Writing `val` to `msr` should not break the kernel.
+unsafe fn wrmsr(msr: u32, val: u64) {
+    // SAFETY: Guaranteed by caller.
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") val as u32,
+            in("edx") (val >> 32) as u32,
+            options(nostack, preserves_flags)
+        )
+    };
+}
@@ -0,0 +1,120 @@
+//! CPU topology from the ACPI MADT.
+//!
+//! Without a MADT, only the boot CPU is known.
+
+use core::fmt::Write as _;
+
+use arrayvec::ArrayVec;
+
+use super::{apic_id, CpuId, MAX_CPUS};
+use crate::drivers::vga::VGA_BUFFER;
+use crate::{acpi, log};
+
+const MADT_ENTRIES_OFFSET: usize = 44;
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LOCAL_X2APIC: u8 = 9;
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+static CPUS: spin::Once<ArrayVec<CpuInfo, MAX_CPUS>> = spin::Once::new();
+
+/// A CPU described by the firmware.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuInfo {
+    /// Dense index among the CPUs.
+    pub id: CpuId,
+    /// Local APIC id, or x2APIC id.
+    pub apic_id: u32,
+    /// ACPI processor uid.
+    pub acpi_id: u32,
+    /// Whether the CPU is enabled, or else only online capable.
+    pub is_enabled: bool,
+}
+
+/// Enumerate the CPUs from the MADT.
+///
+/// This should be called after memory is initialized. The boot CPU is given
+/// id 0 and the other CPUs follow in MADT order. CPUs beyond [`MAX_CPUS`] are
+/// skipped.
+pub fn init() {
+    CPUS.call_once(|| {
+        let mut cpus = ArrayVec::new();
+        cpus.push(CpuInfo {
+            id: 0,
+            apic_id: apic_id(),
+            acpi_id: 0,
+            is_enabled: true,
+        });
+        if let Some(madt) = acpi::find_table(b"APIC") {
+            parse_madt(madt.bytes(), &mut cpus);
+        }
+        cpus
+    });
+    log!("topology: {} cpus\n", cpu_cnt());
+}
+
+/// Returns the CPUs indexed by [`CpuId`]. Empty before [`init`].
+pub fn cpus() -> &'static [CpuInfo] { CPUS.get().map_or(&[], |cpus| cpus.as_slice()) }
+
+/// Returns the number of enabled CPUs, counting at least the boot CPU.
+pub fn cpu_cnt() -> usize { cpus().iter().filter(|cpu| cpu.is_enabled).count().max(1) }
+
+fn parse_madt(madt: &[u8], cpus: &mut ArrayVec<CpuInfo, MAX_CPUS>) {
+    let read_u32 = |entry: &[u8], offset: usize| {
+        entry
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let mut entries = madt.get(MADT_ENTRIES_OFFSET..).unwrap_or_default();
+    while let [kind, len, ..] = *entries {
+        let len = len as usize;
+        if len < 2 || len > entries.len() {
+            break;
+        }
+        let entry = &entries[..len];
+        entries = &entries[len..];
+
+        let (id, acpi_id, flags) = match kind {
+            MADT_LOCAL_APIC => match (
+                entry.get(2),
+                entry.get(3),
+                read_u32(entry, 4),
+            ) {
+                (Some(acpi_id), Some(id), Some(flags)) => (*id as u32, *acpi_id as u32, flags),
+                _ => continue,
+            },
+            MADT_LOCAL_X2APIC => match (
+                read_u32(entry, 4),
+                read_u32(entry, 8),
+                read_u32(entry, 12),
+            ) {
+                (Some(id), Some(flags), Some(acpi_id)) => (id, acpi_id, flags),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        if flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) == 0 {
+            continue;
+        }
+        if let Some(cpu) = cpus.iter_mut().find(|cpu| cpu.apic_id == id) {
+            if cpu.id == 0 {
+                cpu.acpi_id = acpi_id;
+            }
+            continue;
+        }
+        if cpus.is_full() {
+            log!(
+                "topology: skipping cpu with apic id {}\n",
+                id
+            );
+            continue;
+        }
+        cpus.push(CpuInfo {
+            id: cpus.len(),
+            apic_id: id,
+            acpi_id,
+            is_enabled: flags & LOCAL_APIC_ENABLED != 0,
+        });
+    }
+}
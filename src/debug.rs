@@ -0,0 +1,216 @@
+//! Hardware breakpoints through the debug registers DR0-DR3.
+//!
+//! Breakpoints are programmed on the current CPU only.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::{KError, KResult};
+use crate::interrupt::{InterruptGuard, TrapFrame};
+
+/// Number of hardware breakpoint slots.
+pub const BREAKPOINT_CNT: usize = 4;
+
+/// Access which triggers a breakpoint.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakKind {
+    Execute = 0b00,
+    Write = 0b01,
+    /// Triggers on reads and writes. x86 has no read-only breakpoint.
+    ReadWrite = 0b11,
+}
+
+/// Size of the watched region. The address must be aligned to it.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakLen {
+    B1 = 0b00,
+    B2 = 0b01,
+    B4 = 0b11,
+    B8 = 0b10,
+}
+impl BreakLen {
+    pub fn from_bytes(bytes: usize) -> Option<Self> {
+        match bytes {
+            1 => Some(Self::B1),
+            2 => Some(Self::B2),
+            4 => Some(Self::B4),
+            8 => Some(Self::B8),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(self) -> usize {
+        match self {
+            Self::B1 => 1,
+            Self::B2 => 2,
+            Self::B4 => 4,
+            Self::B8 => 8,
+        }
+    }
+}
+
+/// Callback run from the #DB handler when breakpoint `slot` triggers.
+pub type BreakHandler = fn(slot: usize, frame: &mut TrapFrame);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Breakpoint {
+    pub addr: usize,
+    pub kind: BreakKind,
+    pub len: BreakLen,
+    pub handler: Option<BreakHandler>,
+}
+
+/// Hit statistics of a breakpoint slot.
+#[derive(Debug, Clone, Copy)]
+pub struct BreakpointHits {
+    pub cnt: usize,
+    /// Instruction pointer of the last hit. For data breakpoints, this is the
+    /// instruction after the access.
+    pub last_ip: usize,
+}
+
+struct Slot {
+    breakpoint: spin::Mutex<Option<Breakpoint>>,
+    hit_cnt: AtomicUsize,
+    last_ip: AtomicUsize,
+}
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            breakpoint: spin::Mutex::new(None),
+            hit_cnt: AtomicUsize::new(0),
+            last_ip: AtomicUsize::new(0),
+        }
+    }
+}
+
+static SLOTS: [Slot; BREAKPOINT_CNT] = [const { Slot::new() }; BREAKPOINT_CNT];
+
+const DR6_B_MASK: usize = 0b1111;
+const DR6_RESET: usize = 0xFFFF_0FF0;
+const DR7_LEN_BITS: usize = 4;
+const FLAGS_RF: usize = 1 << 16;
+
+/// Set a breakpoint in a free slot, returning the slot.
+///
+/// Fails with [`KError::Inval`] if `addr` is not aligned to `len` or an
+/// execute breakpoint is not 1 byte long, or [`KError::Busy`] if all slots
+/// are used.
+pub fn set_breakpoint(breakpoint: Breakpoint) -> KResult<usize> {
+    if breakpoint.addr % breakpoint.len.bytes() != 0 {
+        return Err(KError::Inval);
+    }
+    if breakpoint.kind == BreakKind::Execute && breakpoint.len != BreakLen::B1 {
+        return Err(KError::Inval);
+    }
+
+    let _guard = InterruptGuard::new();
+    for (idx, slot) in SLOTS.iter().enumerate() {
+        let mut slot_bp = slot.breakpoint.lock();
+        if slot_bp.is_some() {
+            continue;
+        }
+
+        *slot_bp = Some(breakpoint);
+        slot.hit_cnt.store(0, Ordering::Relaxed);
+        slot.last_ip.store(0, Ordering::Relaxed);
+        // SAFETY: Setting a breakpoint does not affect memory safety.
+        unsafe {
+            write_dr_addr(idx, breakpoint.addr);
+            let mut dr7 = read_dr7();
+            dr7 &= !(0b1111 << (16 + idx * DR7_LEN_BITS));
+            dr7 |= (breakpoint.kind as usize) << (16 + idx * DR7_LEN_BITS);
+            dr7 |= (breakpoint.len as usize) << (18 + idx * DR7_LEN_BITS);
+            dr7 |= 1 << (idx * 2);
+            write_dr7(dr7);
+        }
+        return Ok(idx);
+    }
+    Err(KError::Busy)
+}
+
+/// Clear the breakpoint in `slot`, returning it.
+pub fn clear_breakpoint(slot: usize) -> Option<Breakpoint> {
+    let entry = SLOTS.get(slot)?;
+
+    let _guard = InterruptGuard::new();
+    let breakpoint = entry.breakpoint.lock().take()?;
+    // SAFETY: Clearing a breakpoint does not affect memory safety.
+    unsafe {
+        write_dr7(read_dr7() & !(0b11 << (slot * 2)));
+        write_dr_addr(slot, 0);
+    }
+    Some(breakpoint)
+}
+
+/// Returns the breakpoint in `slot` with its hit statistics.
+pub fn breakpoint(slot: usize) -> Option<(Breakpoint, BreakpointHits)> {
+    let entry = SLOTS.get(slot)?;
+
+    let breakpoint = (*entry.breakpoint.lock())?;
+    let hits = BreakpointHits {
+        cnt: entry.hit_cnt.load(Ordering::Relaxed),
+        last_ip: entry.last_ip.load(Ordering::Relaxed),
+    };
+    Some((breakpoint, hits))
+}
+
+/// Handle a debug exception.
+pub(crate) fn handle_debug_exception(frame: &mut TrapFrame) {
+    // SAFETY: Reading and resetting DR6 does not affect memory safety.
+    let dr6 = unsafe { read_dr6() };
+    unsafe { write_dr6(DR6_RESET) };
+    // SAFETY: Reading DR7 does not affect memory safety.
+    let dr7 = unsafe { read_dr7() };
+
+    for (idx, slot) in SLOTS.iter().enumerate() {
+        if dr6 & DR6_B_MASK & (1 << idx) == 0 {
+            continue;
+        }
+        slot.hit_cnt.fetch_add(1, Ordering::Relaxed);
+        slot.last_ip.store(frame.ip, Ordering::Relaxed);
+
+        // Execute breakpoints are faults, so resume without re-triggering.
+        // The kind is read from DR7, since the slot may be locked by the
+        // interrupted code.
+        let kind = (dr7 >> (16 + idx * DR7_LEN_BITS)) & 0b11;
+        if kind == BreakKind::Execute as usize {
+            frame.flags |= FLAGS_RF;
+        }
+
+        let breakpoint = slot.breakpoint.try_lock().and_then(|bp| *bp);
+        if let Some(handler) = breakpoint.and_then(|bp| bp.handler) {
+            handler(idx, frame);
+        }
+    }
+}
+
+unsafe fn write_dr_addr(idx: usize, addr: usize) {
+    unsafe {
+        match idx {
+            0 => asm!("mov dr0, {}", in(reg) addr),
+            1 => asm!("mov dr1, {}", in(reg) addr),
+            2 => asm!("mov dr2, {}", in(reg) addr),
+            3 => asm!("mov dr3, {}", in(reg) addr),
+            _ => unreachable!(),
+        }
+    }
+}
+
+unsafe fn read_dr6() -> usize {
+    let dr6;
+    unsafe { asm!("mov {}, dr6", out(reg) dr6) };
+    dr6
+}
+
+unsafe fn write_dr6(dr6: usize) { unsafe { asm!("mov dr6, {}", in(reg) dr6) } }
+
+unsafe fn read_dr7() -> usize {
+    let dr7;
+    unsafe { asm!("mov {}, dr7", out(reg) dr7) };
+    dr7
+}
+
+unsafe fn write_dr7(dr7: usize) { unsafe { asm!("mov dr7, {}", in(reg) dr7) } }
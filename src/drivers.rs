@@ -1,4 +1,111 @@
+use arrayvec::ArrayVec;
+use serial::{AUX_SERIAL, SERIAL};
+use vga::VGA_BUFFER;
+
+use crate::common::lock;
+use crate::error::KResult;
+use crate::phase::{self, Phase};
+use crate::{klog, time};
+
+pub mod pit;
 pub mod ps2;
+pub mod serial;
 pub mod vga;
 
-pub fn init() { ps2::init(); }
+/// Time a driver may take to shut down before it is reported as slow.
+const SHUTDOWN_BUDGET_MS: u64 = 1000;
+
+const PM_OPS_LEN: usize = 16;
+
+static PM_OPS: spin::Mutex<ArrayVec<&'static PmOps, PM_OPS_LEN>> =
+    spin::Mutex::new(ArrayVec::new_const());
+
+/// Power management callbacks of a driver.
+pub struct PmOps {
+    pub name: &'static str,
+    /// Quiesce the device before the system suspends. A failure aborts the
+    /// suspend.
+    pub suspend: fn() -> KResult<()>,
+    /// Bring the device back after the system resumes.
+    pub resume: fn(),
+    /// Flush and stop the device before the system powers off.
+    pub shutdown: fn(),
+}
+
+pub fn init() {
+    phase::enter(Phase::Drivers);
+    lock::register(&*VGA_BUFFER);
+    lock::register(&*SERIAL);
+    lock::register(&*AUX_SERIAL);
+    // Serial is registered first so that it shuts down last, after the
+    // other drivers have logged.
+    register_pm(&serial::PM_OPS);
+    if let Err(err) = ps2::init() {
+        klog!(
+            Warn,
+            "ps2: keyboard unavailable: {:?}",
+            err
+        );
+    }
+}
+
+/// Register `ops` to be called on suspend, resume and shutdown.
+///
+/// # Panics
+/// Panics if the registry is full.
+pub fn register_pm(ops: &'static PmOps) {
+    PM_OPS
+        .lock()
+        .try_push(ops)
+        .expect("pm registry should not be full");
+}
+
+/// Suspend registered drivers in reverse registration order.
+///
+/// If a driver fails to suspend, the drivers already suspended are resumed
+/// and the error is returned.
+pub fn suspend() -> KResult<()> {
+    let ops = PM_OPS.lock();
+    for (idx, driver) in ops.iter().enumerate().rev() {
+        if let Err(err) = (driver.suspend)() {
+            klog!(
+                Error,
+                "{}: suspend failed: {}",
+                driver.name,
+                err
+            );
+            for driver in &ops[idx + 1..] {
+                (driver.resume)();
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Resume registered drivers in registration order.
+pub fn resume() {
+    for driver in PM_OPS.lock().iter() {
+        (driver.resume)();
+    }
+}
+
+/// Shut down registered drivers in reverse registration order.
+///
+/// A driver going over [`SHUTDOWN_BUDGET_MS`] is reported, but the sequence
+/// still waits for it, as it may be flushing data.
+pub fn shutdown() {
+    for driver in PM_OPS.lock().iter().rev() {
+        let start = time::jiffies();
+        (driver.shutdown)();
+        let elapsed_ms = time::jiffies_to_ms(time::jiffies() - start);
+        if elapsed_ms > SHUTDOWN_BUDGET_MS {
+            klog!(
+                Warn,
+                "{}: shutdown took {} ms",
+                driver.name,
+                elapsed_ms
+            );
+        }
+    }
+}
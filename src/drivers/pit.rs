@@ -0,0 +1,60 @@
+//! Intel 8253/8254 programmable interval timer.
+
+use crate::common::pmio::{inb, outb, Port, WPort};
+use crate::interrupt::InterruptGuard;
+
+/// Input clock of the PIT in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+/// IRQ line of channel 0.
+pub const IRQ: u8 = 0;
+
+const CHANNEL0_PORT: Port = Port(0x40);
+const CHANNEL2_PORT: Port = Port(0x42);
+const CMD_PORT: WPort = WPort(0x43);
+/// NMI status and control port, which gates channel 2.
+const GATE_PORT: Port = Port(0x61);
+
+// Channel 0, lobyte/hibyte access, mode 2 (rate generator), binary counting.
+const CMD_CHANNEL0_RATE: u8 = 0b0011_0100;
+// Channel 2, lobyte/hibyte access, mode 0 (terminal count), binary counting.
+const CMD_CHANNEL2_ONESHOT: u8 = 0b1011_0000;
+
+const GATE_CHANNEL2: u8 = 0b1;
+const GATE_SPEAKER: u8 = 0b10;
+const GATE_CHANNEL2_OUT: u8 = 0b10_0000;
+
+/// Program channel 0 to interrupt periodically at roughly `hz`.
+///
+/// Returns the actual frequency, which is rounded by the integer divisor of
+/// the PIT.
+pub fn init(hz: u32) -> u32 {
+    let divisor = (BASE_FREQUENCY / hz.max(1)).clamp(1, u16::MAX as u32 + 1);
+
+    let _guard = InterruptGuard::new();
+    outb(CMD_PORT, CMD_CHANNEL0_RATE);
+    // A divisor of 0x10000 is written as 0.
+    outb(CHANNEL0_PORT, divisor as u8);
+    outb(CHANNEL0_PORT, (divisor >> 8) as u8);
+
+    BASE_FREQUENCY / divisor
+}
+
+/// Busy-wait until channel 2 counts down `cnt` cycles of [`BASE_FREQUENCY`].
+///
+/// Channel 2 is not connected to an interrupt, so this works with interrupt
+/// disabled and does not disturb the tick.
+pub fn wait_cycles(cnt: u16) {
+    let _guard = InterruptGuard::new();
+    let gate = inb(GATE_PORT) & !GATE_SPEAKER;
+    outb(GATE_PORT, gate & !GATE_CHANNEL2);
+    outb(CMD_PORT, CMD_CHANNEL2_ONESHOT);
+    outb(CHANNEL2_PORT, cnt as u8);
+    outb(CHANNEL2_PORT, (cnt >> 8) as u8);
+    // Counting starts at the rising edge of the gate.
+    outb(GATE_PORT, gate | GATE_CHANNEL2);
+
+    while inb(GATE_PORT) & GATE_CHANNEL2_OUT == 0 {
+        core::hint::spin_loop();
+    }
+    outb(GATE_PORT, gate & !GATE_CHANNEL2);
+}
@@ -1,64 +1,275 @@
+//! PS/2 controller and keyboard.
+//!
+//! The IRQ handler only queues raw scancode bytes and wakes the bottom half,
+//! an [`executor`] task parsing them into key events, to keep the time spent
+//! in hard IRQ context minimal.
+
 use core::cell::SyncUnsafeCell;
 use core::fmt::Write as _;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use arraydeque::ArrayDeque;
 use arrayvec::ArrayVec;
 use ringbuf::traits::{Consumer, Producer, Split, SplitRef};
 use ringbuf::HeapRb as Rb;
 
-use crate::common::pmio::{inb, Port, RPort, WPort};
+use super::PmOps;
+use crate::common::pmio::{inb, outb, Port, RPort, WPort};
 use crate::drivers::vga::VGA_BUFFER;
-use crate::interrupt::InterruptGuard;
+use crate::error::KResult;
+use crate::executor::{self, WaitQueue};
+use crate::interrupt::{self, InterruptGuard, IrqReturn};
 use crate::io::keyboard::keycode::*;
 use crate::io::keyboard::{KeyEvent, Keyboard, VirtKeyboard};
-use crate::log;
+use crate::{log, time};
 
 const DATA_PORT: Port = Port(0x60);
 const STATUS_PORT: RPort = RPort(0x64);
 const CMD_PORT: WPort = WPort(0x64);
 
-static KEYBOARD_SRC: spin::Once<SyncUnsafeCell<Ps2KeyboardSrc>> = spin::Once::new();
+/// Status register bit set when the output buffer holds a byte for the host.
+const STATUS_OUTPUT_FULL: u8 = 0b1;
+/// Status register bit set when the input buffer holds a byte for the
+/// controller.
+const STATUS_INPUT_FULL: u8 = 0b10;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_PORT2: u8 = 0xA7;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_PORT1: u8 = 0xAB;
+const CMD_DISABLE_PORT1: u8 = 0xAD;
+const CMD_ENABLE_PORT1: u8 = 0xAE;
+const CMD_PULSE_RESET: u8 = 0xFE;
+
+const CONFIG_PORT1_IRQ: u8 = 0b1;
+const CONFIG_PORT2_IRQ: u8 = 0b10;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const DEV_RESET: u8 = 0xFF;
+const DEV_ACK: u8 = 0xFA;
+const DEV_SELF_TEST_PASSED: u8 = 0xAA;
+
+/// Time the controller has to accept or answer a command.
+const CMD_TIMEOUT_MS: u64 = 50;
+/// Time a device has to finish its power-on self test after a reset.
+const DEV_RESET_TIMEOUT_MS: u64 = 1000;
+const POLL_INTERVAL_US: u64 = 10;
+
+pub const KEYBOARD_IRQ: u8 = 1;
+
+/// Length of the raw scancode queue between the IRQ handler and the bottom
+/// half.
+const SCANCODE_QUEUE_LEN: usize = 128;
+/// Length of the key event queue between the bottom half and readers.
+const EVENT_QUEUE_LEN: usize = 64;
+
+static SCANCODE_PROD: spin::Once<spin::Mutex<<Rb<u8> as Split>::Prod>> = spin::Once::new();
+/// Woken when a scancode byte is queued.
+static SCANCODE_WAIT: WaitQueue = WaitQueue::new();
+/// Set when the keyboard is reset, so that the bottom half drops its partial
+/// scancode and the bytes queued before the reset.
+static SC_RESET: AtomicBool = AtomicBool::new(false);
 pub static KEYBOARD: spin::Once<SyncUnsafeCell<Ps2Keyboard>> = spin::Once::new();
 
-// TODO: Properly initialize ps2
-pub fn init() {
-    let key_buffer = Rb::new(128);
-    let (prod, cons) = key_buffer.split();
-    KEYBOARD_SRC.call_once(|| {
-        SyncUnsafeCell::new(Ps2KeyboardSrc {
-            cur_sc: SyncUnsafeCell::new(Sc::Sc1(Sc1::Normal)),
-            prod,
-        })
-    });
-    KEYBOARD.call_once(|| {
-        SyncUnsafeCell::new(Ps2Keyboard {
-            virt: VirtKeyboard::new(),
-            src: cons,
-        })
-    });
-}
-
-/// FIXME: UB on multiprocessor
-pub fn ps2_keyboard_handler() {
+/// The keyboard is the wake device, so it is kept enabled while suspended.
+static PM_OPS: PmOps = PmOps {
+    name: "ps2",
+    suspend,
+    resume,
+    shutdown,
+};
+
+/// Failure to bring up the PS/2 controller or keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller did not respond in time.
+    Timeout,
+    /// The controller failed its self test.
+    ControllerSelfTest,
+    /// The first port failed its interface test.
+    PortTest,
+    /// No keyboard answered a reset on the first port.
+    NoKeyboard,
+}
+
+/// Set up the keyboard buffers and probe the controller.
+///
+/// The keyboard is usable once this returns `Ok`. On failure, the controller
+/// can be probed again with [`reinit`].
+pub fn init() -> Result<(), Ps2Error> {
+    let (scancode_prod, scancode_cons) = Rb::new(SCANCODE_QUEUE_LEN).split();
+    let (event_prod, event_cons) = Rb::new(EVENT_QUEUE_LEN).split();
+    SCANCODE_PROD.call_once(|| spin::Mutex::new(scancode_prod));
+    KEYBOARD.call_once(|| SyncUnsafeCell::new(Ps2Keyboard { src: event_cons }));
+    executor::spawn(bottom_half(scancode_cons, event_prod))
+        .expect("keyboard bottom half should be spawned");
+    interrupt::register_irq(KEYBOARD_IRQ, ps2_keyboard_handler)
+        .expect("keyboard irq line should have a free handler slot");
+    super::register_pm(&PM_OPS);
+
+    // The line is unmasked after probing, so the handler does not take
+    // responses to probe commands as scancodes.
+    let res = probe();
+    interrupt::unmask_irq(KEYBOARD_IRQ).expect("keyboard irq should be a valid line");
+    res
+}
+
+/// Reset and re-initialize the controller and keyboard.
+pub fn reinit() -> Result<(), Ps2Error> {
+    // The line may already be masked by shutdown.
+    interrupt::mask_irq(KEYBOARD_IRQ).ok();
+    let res = probe();
+    interrupt::unmask_irq(KEYBOARD_IRQ).expect("keyboard irq should be a valid line");
+    res
+}
+
+/// Pulse the CPU reset line through the controller.
+pub fn pulse_reset() {
+    if wait_input_empty().is_ok() {
+        outb(CMD_PORT, CMD_PULSE_RESET);
+    }
+}
+
+fn probe() -> Result<(), Ps2Error> {
+    // Keep the controller from raising interrupts while responses are polled.
+    send_cmd(CMD_DISABLE_PORT1)?;
+    send_cmd(CMD_DISABLE_PORT2)?;
+    flush_output();
+
+    send_cmd(CMD_READ_CONFIG)?;
+    let config = read_data(CMD_TIMEOUT_MS)? & !(CONFIG_PORT1_IRQ | CONFIG_PORT2_IRQ);
+    write_config(config)?;
+
+    send_cmd(CMD_SELF_TEST)?;
+    if read_data(CMD_TIMEOUT_MS)? != SELF_TEST_PASSED {
+        return Err(Ps2Error::ControllerSelfTest);
+    }
+    // Self test may reset the controller.
+    write_config(config)?;
+
+    send_cmd(CMD_TEST_PORT1)?;
+    if read_data(CMD_TIMEOUT_MS)? != PORT_TEST_PASSED {
+        return Err(Ps2Error::PortTest);
+    }
+    send_cmd(CMD_ENABLE_PORT1)?;
+
+    write_data(DEV_RESET)?;
+    let is_reset = read_data(CMD_TIMEOUT_MS) == Ok(DEV_ACK)
+        && read_data(DEV_RESET_TIMEOUT_MS) == Ok(DEV_SELF_TEST_PASSED);
+    if !is_reset {
+        return Err(Ps2Error::NoKeyboard);
+    }
+
+    SC_RESET.store(true, Ordering::Release);
+    SCANCODE_WAIT.wake_all();
+    write_config(config | CONFIG_PORT1_IRQ)
+}
+
+/// Drop bytes left in the controller, so that a stale byte does not wake the
+/// system right away.
+fn suspend() -> KResult<()> {
+    let _guard = InterruptGuard::new();
+    flush_output();
+    Ok(())
+}
+
+fn resume() {}
+
+/// Stop the keyboard from sending scancodes and mask its line.
+fn shutdown() {
+    let _guard = InterruptGuard::new();
+    send_cmd(CMD_DISABLE_PORT1).ok();
+    flush_output();
+    interrupt::mask_irq(KEYBOARD_IRQ).ok();
+}
+
+fn send_cmd(cmd: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    outb(CMD_PORT, cmd);
+    Ok(())
+}
+
+fn write_data(data: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    outb(DATA_PORT, data);
+    Ok(())
+}
+
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    send_cmd(CMD_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+fn read_data(timeout_ms: u64) -> Result<u8, Ps2Error> {
+    poll_status(timeout_ms, |status| {
+        status & STATUS_OUTPUT_FULL != 0
+    })?;
+    Ok(inb(DATA_PORT))
+}
+
+fn wait_input_empty() -> Result<(), Ps2Error> {
+    poll_status(CMD_TIMEOUT_MS, |status| {
+        status & STATUS_INPUT_FULL == 0
+    })
+}
+
+fn flush_output() {
+    while inb(STATUS_PORT) & STATUS_OUTPUT_FULL != 0 {
+        inb(DATA_PORT);
+    }
+}
+
+fn poll_status(timeout_ms: u64, is_ready: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+    for _ in 0..timeout_ms * 1000 / POLL_INTERVAL_US {
+        if is_ready(inb(STATUS_PORT)) {
+            return Ok(());
+        }
+        time::delay_us(POLL_INTERVAL_US);
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Queue the scancode byte for the bottom half. A byte is dropped if the
+/// queue is full.
+pub fn ps2_keyboard_handler() -> IrqReturn {
+    if inb(STATUS_PORT) & STATUS_OUTPUT_FULL == 0 {
+        return IrqReturn::NotMine;
+    }
+
     let byte = inb(DATA_PORT);
-    let Some(src) = KEYBOARD_SRC.get() else {
-        return;
-    };
-    let src = unsafe { src.get().as_mut_unchecked() };
-    let sc = unsafe { src.cur_sc.get().as_mut_unchecked() };
-    let Some(packet) = sc.parse(byte) else {
-        return;
-    };
-    if !packet.1 {
-        foo();
+    if let Some(prod) = SCANCODE_PROD.get() {
+        prod.lock().try_push(byte).ok();
+        SCANCODE_WAIT.wake_all();
+    }
+    IrqReturn::Handled
+}
+
+/// Parse queued scancode bytes into key events. An event is dropped if the
+/// event queue is full.
+async fn bottom_half(mut src: <Rb<u8> as Split>::Cons, mut dst: <Rb<KeyEvent> as Split>::Prod) {
+    let mut virt = VirtKeyboard::new();
+    let mut sc = Sc::Sc1(Sc1::Normal);
+    loop {
+        let byte = SCANCODE_WAIT
+            .wait_until(|| {
+                if SC_RESET.swap(false, Ordering::Acquire) {
+                    sc = Sc::Sc1(Sc1::Normal);
+                    src.clear();
+                }
+                src.try_pop()
+            })
+            .await;
+        if let Some(event) = sc.parse(byte).and_then(|packet| virt.parse(packet)) {
+            dst.try_push(event).ok();
+        }
     }
-    src.prod.try_push(packet);
 }
-fn foo() {}
 
 pub struct Ps2Keyboard {
-    virt: VirtKeyboard,
-    src: <Rb<(KeyCode, bool)> as Split>::Cons,
+    src: <Rb<KeyEvent> as Split>::Cons,
 }
 
 // FIXME: Temporary workaround, not safe!
@@ -67,18 +278,9 @@ impl Keyboard for Ps2Keyboard {}
 impl Iterator for Ps2Keyboard {
     type Item = KeyEvent;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let packet = self.src.try_pop()?;
-        self.virt.parse(packet)
-    }
-}
-
-struct Ps2KeyboardSrc {
-    cur_sc: SyncUnsafeCell<Sc>,
-    prod: <Rb<(KeyCode, bool)> as Split>::Prod,
+    /// Returns the next key event parsed by the bottom half.
+    fn next(&mut self) -> Option<Self::Item> { self.src.try_pop() }
 }
-// FIXME: Temporary workaround, not safe!
-unsafe impl Sync for Ps2KeyboardSrc {}
 
 enum Sc {
     Sc1(Sc1),
@@ -116,21 +318,56 @@ impl Sc1 {
                 0x39 => Some((KEY_SPACE, true)),
                 0xB9 => Some((KEY_SPACE, false)),
                 0xE0 => {
-                    // *sc1 = Sc1::Extra(0xE0);
+                    *sc1 = Sc1::Extra(0xE0);
                     None
                 },
                 0xE1 => {
-                    // *sc1 = Sc1::Pause(0xE1);
+                    *sc1 = Sc1::Pause(PAUSE_LEN - 1);
                     None
                 },
                 _ => None, // Not parsed
             }
         }
 
+        fn parse_extra(byte: u8) -> Option<(KeyCode, bool)> {
+            let is_press = byte & 0x80 == 0;
+            let key = match byte & 0x7F {
+                0x1C => KEY_KPENTER,
+                0x1D => KEY_RIGHTCTRL,
+                0x35 => KEY_KPSLASH,
+                0x38 => KEY_RIGHTALT,
+                0x47 => KEY_HOME,
+                0x48 => KEY_UP,
+                0x49 => KEY_PAGEUP,
+                0x4B => KEY_LEFT,
+                0x4D => KEY_RIGHT,
+                0x4F => KEY_END,
+                0x50 => KEY_DOWN,
+                0x51 => KEY_PAGEDOWN,
+                0x52 => KEY_INSERT,
+                0x53 => KEY_DELETE,
+                // Including the fake shifts around print screen.
+                _ => return None,
+            };
+            Some((key, is_press))
+        }
+
+        /// Length of the pause sequence, which has no release.
+        const PAUSE_LEN: u8 = 6;
+
         match self {
             Sc1::Normal => parse_normal(self, byte),
-            Sc1::Extra(_) => todo!(),
-            Sc1::Pause(_) => todo!(),
+            Sc1::Extra(_) => {
+                *self = Sc1::Normal;
+                parse_extra(byte)
+            },
+            Sc1::Pause(left) => {
+                *left -= 1;
+                if *left == 0 {
+                    *self = Sc1::Normal;
+                }
+                None
+            },
             Sc1::Command => todo!(),
         }
     }
@@ -0,0 +1,162 @@
+//! 16550 UARTs on COM1 and COM2, used as polled debug ports.
+
+use core::fmt::{self, Write};
+
+use super::PmOps;
+use crate::common::lock::Mutex;
+use crate::common::pmio::{inb, outb, Port};
+use crate::error::KResult;
+
+const COM1: u16 = 0x3F8;
+const COM2: u16 = 0x2F8;
+
+const DATA_OFFSET: u16 = 0;
+/// Interrupt enable register, or the divisor high byte with DLAB set.
+const IER_OFFSET: u16 = 1;
+const FCR_OFFSET: u16 = 2;
+const LCR_OFFSET: u16 = 3;
+const MCR_OFFSET: u16 = 4;
+const LSR_OFFSET: u16 = 5;
+
+/// Divisor latch access bit.
+const LCR_DLAB: u8 = 0x80;
+/// 8 data bits, no parity, 1 stop bit.
+const LCR_8N1: u8 = 0x03;
+/// Enable and clear the FIFOs, with a 14 byte threshold.
+const FCR_ENABLE: u8 = 0xC7;
+/// DTR and RTS.
+const MCR_READY: u8 = 0x03;
+const LSR_THR_EMPTY: u8 = 0x20;
+/// Both the holding and the shift registers are empty.
+const LSR_TX_EMPTY: u8 = 0x40;
+
+const BACKSPACE: u8 = 0x8;
+
+/// Divisor of the 115200 baud base clock, giving 115200 baud.
+const BAUD_DIVISOR: u16 = 1;
+
+pub static SERIAL: spin::Lazy<Mutex<Serial>> =
+    spin::Lazy::new(|| Mutex::new("serial", unsafe { Serial::init(COM1) }));
+
+/// COM2, kept apart from the debug output on [`SERIAL`] for machine-readable
+/// output.
+pub static AUX_SERIAL: spin::Lazy<Mutex<Serial>> = spin::Lazy::new(|| {
+    Mutex::new("aux serial", unsafe {
+        Serial::init(COM2)
+    })
+});
+
+pub static PM_OPS: PmOps = PmOps {
+    name: "serial",
+    suspend,
+    resume,
+    shutdown,
+};
+
+pub struct Serial {
+    base: u16,
+    /// Column of the terminal cursor, so that a backspace does not erase
+    /// past the start of the line.
+    column: usize,
+}
+impl Serial {
+    /// Program the UART at I/O port `base` for polled output.
+    ///
+    /// # Safety
+    /// Since `Serial` owns the UART, there should be only one `Serial` for
+    /// `base` in existence.
+    pub unsafe fn init(base: u16) -> Self {
+        let port = |offset| Port(base + offset);
+        outb(port(IER_OFFSET), 0);
+        outb(port(LCR_OFFSET), LCR_DLAB);
+        outb(port(DATA_OFFSET), BAUD_DIVISOR as u8);
+        outb(
+            port(IER_OFFSET),
+            (BAUD_DIVISOR >> 8) as u8,
+        );
+        outb(port(LCR_OFFSET), LCR_8N1);
+        outb(port(FCR_OFFSET), FCR_ENABLE);
+        outb(port(MCR_OFFSET), MCR_READY);
+        Self { base, column: 0 }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) { write_byte_at(self.base, byte) }
+
+    /// Wait until every written byte has left the UART.
+    pub fn flush(&mut self) {
+        while inb(Port(self.base + LSR_OFFSET)) & LSR_TX_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+impl Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            match byte {
+                b'\n' => {
+                    self.write_byte(b'\r');
+                    self.write_byte(b'\n');
+                    self.column = 0;
+                },
+                BACKSPACE => {
+                    // Terminals only move the cursor back on a backspace.
+                    if self.column > 0 {
+                        self.write_byte(BACKSPACE);
+                        self.write_byte(b' ');
+                        self.write_byte(BACKSPACE);
+                        self.column -= 1;
+                    }
+                },
+                _ => {
+                    self.write_byte(byte);
+                    self.column += 1;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A writer to COM1 which does not hold [`SERIAL`], for contexts which
+/// cannot take locks.
+pub struct RawSerial;
+impl Write for RawSerial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                write_byte_raw(b'\r');
+            }
+            write_byte_raw(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Write `byte` to COM1 without holding [`SERIAL`].
+///
+/// This is meant for contexts which cannot take locks. COM1 should have been
+/// initialized through [`SERIAL`].
+pub fn write_byte_raw(byte: u8) { write_byte_at(COM1, byte) }
+
+/// Drain both UARTs, so that no output is lost while suspended.
+fn suspend() -> KResult<()> {
+    shutdown();
+    Ok(())
+}
+
+/// The UARTs keep their state while suspended to idle.
+fn resume() {}
+
+/// Drain both UARTs, so that the last messages reach the host before power
+/// off.
+fn shutdown() {
+    SERIAL.lock().flush();
+    AUX_SERIAL.lock().flush();
+}
+
+fn write_byte_at(base: u16, byte: u8) {
+    while inb(Port(base + LSR_OFFSET)) & LSR_THR_EMPTY == 0 {
+        core::hint::spin_loop();
+    }
+    outb(Port(base + DATA_OFFSET), byte);
+}
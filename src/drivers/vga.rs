@@ -1,7 +1,11 @@
 use core::fmt::Write;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
 
-use crate::common::pmio::{outb, Port};
+use crate::common::lock::Mutex;
+use crate::common::pmio::{inb, outb, Port};
 use crate::mem::kernel_offset_vma;
+use crate::time;
 
 /// Address of start of VGA MMIO
 const BUFFER: usize = kernel_offset_vma() + 0xb8000;
@@ -15,14 +19,27 @@ const VIEW_HEIGHT: usize = 25;
 /// Width of terminal
 const VIEW_WIDTH: usize = 80;
 
+const VIEW_LEN: usize = VIEW_HEIGHT * VIEW_WIDTH;
+
 const CRTC_ADDR_PORT: Port = Port(0x3D4);
 const CRTC_DATA_PORT: Port = Port(0x3D5);
 
+const CURSOR_START_IDX: u8 = 0xA;
 const CURSOR_LOC_HIGH_IDX: u8 = 0xE;
 const CURSOR_LOC_LOW_IDX: u8 = 0xF;
 
-pub static VGA_BUFFER: spin::Lazy<spin::Mutex<VGABuffer>> =
-    spin::Lazy::new(|| spin::Mutex::new(unsafe { VGABuffer::init() }));
+/// Cursor start register bit hiding the cursor.
+const CURSOR_DISABLE: u8 = 0x20;
+
+/// Period of the console refresh timer.
+const REFRESH_MS: u64 = 20;
+/// Cursor blink period in refreshes.
+const BLINK_REFRESHES: u32 = 25;
+
+static REFRESH_CNT: AtomicU32 = AtomicU32::new(0);
+
+pub static VGA_BUFFER: spin::Lazy<Mutex<VGABuffer>> =
+    spin::Lazy::new(|| Mutex::new("vga", unsafe { VGABuffer::init() }));
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -43,6 +60,14 @@ pub struct VGABuffer {
     /// background color.
     color_code: u8,
     cursor_pos: u16,
+    /// Screen contents, copied to `buffer` by [`Self::flush`].
+    shadow: [u16; VIEW_LEN],
+    /// Entries of `shadow` not yet copied to `buffer`.
+    dirty: Range<usize>,
+    is_cursor_dirty: bool,
+    is_cursor_shown: bool,
+    /// Whether flushing is left to the refresh timer.
+    is_batched: bool,
     buffer: &'static mut [u16],
 }
 impl VGABuffer {
@@ -56,18 +81,18 @@ impl VGABuffer {
         use Color::*;
 
         let color_code = color_code(Black, Black, false);
-        let buffer = unsafe {
-            core::slice::from_raw_parts_mut(
-                BUFFER as *mut u16,
-                VIEW_HEIGHT * VIEW_WIDTH,
-            )
-        };
+        let buffer = unsafe { core::slice::from_raw_parts_mut(BUFFER as *mut u16, VIEW_LEN) };
         let filler = vga_entry(color_code, 0);
         buffer.fill(filler);
 
         VGABuffer {
             color_code,
             cursor_pos: 0,
+            shadow: [filler; VIEW_LEN],
+            dirty: 0..0,
+            is_cursor_dirty: false,
+            is_cursor_shown: true,
+            is_batched: false,
             buffer,
         }
     }
@@ -75,20 +100,25 @@ impl VGABuffer {
     /// Clears the VGA buffer by filling it with spaces of the specified color.
     pub fn clear(&mut self) {
         let filler = vga_entry(self.color_code, 0);
-        self.buffer.fill(filler);
+        self.shadow.fill(filler);
+        self.mark_dirty(0..VIEW_LEN);
         self.set_cursor_pos(0, 0);
-        self.sync_cursor();
     }
 
     pub fn set_color(&mut self, fg: Color, bg: Color, is_bright: bool) {
         self.color_code = color_code(fg, bg, is_bright);
     }
 
+    pub fn color_code(&self) -> u8 { self.color_code }
+
+    pub fn set_color_code(&mut self, color_code: u8) { self.color_code = color_code; }
+
     pub fn set_cursor_pos(&mut self, x: u8, y: u8) {
         let new_pos = x as u16 * y as u16;
         assert!(new_pos < VIEW_HEIGHT as u16 * VIEW_WIDTH as u16);
         self.cursor_pos = new_pos;
-        self.sync_cursor();
+        self.is_cursor_dirty = true;
+        self.flush_if_unbatched();
     }
 
     pub fn get_cursor_pos(&self) -> (u8, u8) {
@@ -100,6 +130,43 @@ impl VGABuffer {
     pub const fn viewport_dim(&self) -> (u8, u8) { (VIEW_WIDTH as u8, VIEW_HEIGHT as u8) }
 
     pub fn write_u8(&mut self, char: u8) {
+        self.put_u8(char);
+        self.flush_if_unbatched();
+    }
+
+    pub fn write(&mut self, text: &[u8]) {
+        for &char in text {
+            self.put_u8(char);
+        }
+        self.flush_if_unbatched();
+    }
+
+    /// Copy pending changes to the screen and move the hardware cursor.
+    pub fn flush(&mut self) {
+        let dirty = self.dirty.clone();
+        self.buffer[dirty.clone()].copy_from_slice(&self.shadow[dirty]);
+        self.dirty = 0..0;
+        if self.is_cursor_dirty {
+            self.sync_cursor();
+            self.is_cursor_dirty = false;
+        }
+    }
+
+    fn flush_if_unbatched(&mut self) {
+        if !self.is_batched {
+            self.flush();
+        }
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        if self.dirty.is_empty() {
+            self.dirty = range;
+        } else {
+            self.dirty = self.dirty.start.min(range.start)..self.dirty.end.max(range.end);
+        }
+    }
+
+    fn put_u8(&mut self, char: u8) {
         if self.cursor_pos == VIEW_HEIGHT as u16 * VIEW_WIDTH as u16 {
             return;
         }
@@ -107,46 +174,53 @@ impl VGABuffer {
         match char {
             b'\n' => {
                 self.cursor_pos = self.cursor_pos.next_multiple_of(VIEW_WIDTH as u16);
-                if self.cursor_pos >= self.buffer.len() as u16 {
+                if self.cursor_pos >= VIEW_LEN as u16 {
                     self.scroll_up();
                 }
             },
             0x8 => {
                 if self.cursor_pos != 0 {
                     self.cursor_pos -= 1;
-                    self.buffer[self.cursor_pos as usize] = vga_entry(self.color_code, b'\0');
+                    let pos = self.cursor_pos as usize;
+                    self.shadow[pos] = vga_entry(self.color_code, b'\0');
+                    self.mark_dirty(pos..pos + 1);
                 }
                 while self.cursor_pos > 0
-                    && entry_get_char(self.buffer[(self.cursor_pos - 1) as usize]) == b'\0'
+                    && entry_get_char(self.shadow[(self.cursor_pos - 1) as usize]) == b'\0'
                 {
                     self.cursor_pos -= 1;
                 }
-                self.sync_cursor();
             },
             _ => {
-                self.buffer[self.cursor_pos as usize] = vga_entry(self.color_code, char);
+                let pos = self.cursor_pos as usize;
+                self.shadow[pos] = vga_entry(self.color_code, char);
+                self.mark_dirty(pos..pos + 1);
                 self.cursor_pos += 1;
-                self.sync_cursor();
             },
         }
-    }
-
-    pub fn write(&mut self, text: &[u8]) {
-        for &char in text {
-            self.write_u8(char);
-        }
+        self.is_cursor_dirty = true;
     }
 
     fn scroll_up(&mut self) {
         let width = VIEW_WIDTH as usize;
-        self.buffer.copy_within(width.., 0);
+        self.shadow.copy_within(width.., 0);
 
         let filler = vga_entry(self.color_code, 0);
-        let buffer_len = self.buffer.len();
-        self.buffer[(buffer_len - width)..].fill(filler);
+        self.shadow[(VIEW_LEN - width)..].fill(filler);
+        self.mark_dirty(0..VIEW_LEN);
         self.cursor_pos -= VIEW_WIDTH as u16;
+    }
 
-        self.sync_cursor();
+    fn toggle_cursor(&mut self) {
+        self.is_cursor_shown = !self.is_cursor_shown;
+        outb(CRTC_ADDR_PORT, CURSOR_START_IDX);
+        let start = inb(CRTC_DATA_PORT);
+        let start = if self.is_cursor_shown {
+            start & !CURSOR_DISABLE
+        } else {
+            start | CURSOR_DISABLE
+        };
+        outb(CRTC_DATA_PORT, start);
     }
 
     fn sync_cursor(&mut self) {
@@ -165,13 +239,37 @@ impl VGABuffer {
 
 impl Write for VGABuffer {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for &c in s.as_bytes() {
-            self.write_u8(c);
-        }
+        self.write(s.as_bytes());
         Ok(())
     }
 }
 
+/// Leave flushing [`VGA_BUFFER`] to a periodic refresh timer, which also
+/// blinks the cursor. Until then, every write is flushed right away.
+///
+/// This should be called after [`time::init`].
+pub fn start_refresh() {
+    VGA_BUFFER.lock().is_batched = true;
+    schedule_refresh();
+}
+
+fn schedule_refresh() {
+    let expires = time::jiffies() + time::ms_to_jiffies(REFRESH_MS);
+    time::add_timer(expires, refresh).expect("timer table should have room for console refresh");
+}
+
+fn refresh() {
+    // The interrupted code may hold the lock, in which case the changes are
+    // left to the next refresh.
+    if let Some(mut vga_buffer) = VGA_BUFFER.try_lock() {
+        vga_buffer.flush();
+        if REFRESH_CNT.fetch_add(1, Ordering::Relaxed) % BLINK_REFRESHES == 0 {
+            vga_buffer.toggle_cursor();
+        }
+    }
+    schedule_refresh();
+}
+
 fn vga_entry(color_code: u8, char: u8) -> u16 { ((color_code as u16) << 8) + char as u16 }
 fn entry_get_char(entry: u16) -> u8 { (entry & 0x00FF) as u8 }
 
@@ -0,0 +1,53 @@
+//! Kernel-wide error type.
+//!
+//! Fallible kernel APIs return [`KResult`] so that callers can propagate
+//! failures with `?`, and syscalls can report them to user space through
+//! [`KError::errno`].
+
+use core::fmt;
+
+/// A kernel error, named after the errno value it translates to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KError {
+    /// No such file or directory.
+    NoEnt,
+    /// Bad address.
+    Fault,
+    /// Device or resource busy.
+    Busy,
+    /// Out of memory.
+    NoMem,
+    /// Invalid argument.
+    Inval,
+    /// No space left in a fixed-size table.
+    NoSpc,
+}
+impl KError {
+    /// Returns the errno value reported to user space.
+    pub const fn errno(self) -> i32 {
+        match self {
+            Self::NoEnt => 2,
+            Self::Fault => 14,
+            Self::Busy => 16,
+            Self::NoMem => 12,
+            Self::Inval => 22,
+            Self::NoSpc => 28,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::NoEnt => "ENOENT",
+            Self::Fault => "EFAULT",
+            Self::Busy => "EBUSY",
+            Self::NoMem => "ENOMEM",
+            Self::Inval => "EINVAL",
+            Self::NoSpc => "ENOSPC",
+        }
+    }
+}
+impl fmt::Display for KError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.name()) }
+}
+
+pub type KResult<T> = Result<T, KError>;
@@ -0,0 +1,198 @@
+//! Executor of `async` kernel tasks.
+//!
+//! Long-running driver sequences can be written as `async fn` state machines
+//! and [`spawn`]ed, instead of needing a kthread or nested timer callbacks.
+//! Tasks are polled by [`run_ready`] whenever the CPU would otherwise idle in
+//! [`time::wait_until`], and are woken by [`sleep_ms`] timers or by a
+//! [`WaitQueue`] signalled from an interrupt handler.
+
+use alloc::boxed::Box;
+use core::future::{self, Future};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use arrayvec::ArrayVec;
+
+use crate::error::{KError, KResult};
+use crate::interrupt::InterruptGuard;
+use crate::time;
+
+const TASKS_LEN: usize = u64::BITS as usize;
+const SLEEPERS_LEN: usize = 16;
+const WAITERS_LEN: usize = 8;
+
+type BoxTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+static TASKS: spin::Mutex<[Option<BoxTask>; TASKS_LEN]> =
+    spin::Mutex::new([const { None }; TASKS_LEN]);
+/// Tasks to be polled, one bit per slot of [`TASKS`].
+static READY: AtomicU64 = AtomicU64::new(0);
+
+static SLEEPERS: spin::Mutex<ArrayVec<(u64, Waker), SLEEPERS_LEN>> =
+    spin::Mutex::new(ArrayVec::new_const());
+
+static WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    clone_waker,
+    wake_task,
+    wake_task,
+    drop_waker,
+);
+
+/// Spawn `task` to be run by the executor.
+///
+/// Fails with [`KError::NoSpc`] if the task table is full, or
+/// [`KError::NoMem`] if the task cannot be allocated.
+///
+/// ```kexample
+/// use core::sync::atomic::{AtomicBool, Ordering};
+///
+/// static IS_DONE: AtomicBool = AtomicBool::new(false);
+///
+/// crate::executor::spawn(async {
+///     crate::executor::sleep_ms(10).await;
+///     IS_DONE.store(true, Ordering::Relaxed);
+/// })
+/// .expect("task table should not be full");
+/// crate::time::wait_until(|| IS_DONE.load(Ordering::Relaxed).then_some(()));
+/// ```
+pub fn spawn(task: impl Future<Output = ()> + Send + 'static) -> KResult<()> {
+    let task: BoxTask = Box::into_pin(Box::try_new(task).map_err(|_| KError::NoMem)?);
+
+    let _guard = InterruptGuard::new();
+    let mut tasks = TASKS.lock();
+    let idx = tasks
+        .iter()
+        .position(Option::is_none)
+        .ok_or(KError::NoSpc)?;
+    tasks[idx] = Some(task);
+    READY.fetch_or(1 << idx, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Poll every woken task once.
+///
+/// Tasks are polled with interrupt enabled and without holding the task
+/// table, so they may spawn other tasks.
+pub fn run_ready() {
+    let ready = READY.swap(0, Ordering::Acquire);
+    for idx in (0..TASKS_LEN).filter(|idx| ready & (1 << idx) != 0) {
+        let task = {
+            let _guard = InterruptGuard::new();
+            TASKS.lock()[idx].take()
+        };
+        let Some(mut task) = task else {
+            continue;
+        };
+
+        // SAFETY: The vtable functions treat the data as a slot index.
+        let waker = unsafe {
+            Waker::from_raw(RawWaker::new(
+                idx as *const (),
+                &WAKER_VTABLE,
+            ))
+        };
+        if task
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending()
+        {
+            let _guard = InterruptGuard::new();
+            TASKS.lock()[idx] = Some(task);
+        }
+    }
+}
+
+/// Check if any task is woken and waiting to be polled.
+pub fn has_ready() -> bool { READY.load(Ordering::Relaxed) != 0 }
+
+/// Returns a future completing once `ms` milliseconds have passed.
+pub fn sleep_ms(ms: u64) -> impl Future<Output = ()> {
+    let expires = time::jiffies() + time::ms_to_jiffies(ms);
+    let mut is_armed = false;
+    future::poll_fn(move |cx| {
+        if time::jiffies() >= expires {
+            return Poll::Ready(());
+        }
+        if !is_armed {
+            is_armed = arm_sleeper(expires, cx.waker());
+        }
+        if !is_armed {
+            // Without a timer, the task polls until it expires.
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    })
+}
+
+/// Tasks waiting for a condition signalled from elsewhere, typically an
+/// interrupt handler.
+pub struct WaitQueue {
+    waiters: spin::Mutex<ArrayVec<Waker, WAITERS_LEN>>,
+}
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: spin::Mutex::new(ArrayVec::new_const()),
+        }
+    }
+
+    /// Returns a future completing with the value of `poll` once it returns
+    /// `Some`. `poll` is checked again whenever the queue is woken.
+    pub fn wait_until<'a, T>(
+        &'a self,
+        mut poll: impl FnMut() -> Option<T> + 'a,
+    ) -> impl Future<Output = T> + 'a {
+        future::poll_fn(move |cx| {
+            let _guard = InterruptGuard::new();
+            if let Some(val) = poll() {
+                return Poll::Ready(val);
+            }
+            let mut waiters = self.waiters.lock();
+            if !waiters.iter().any(|waker| waker.will_wake(cx.waker())) {
+                if let Err(err) = waiters.try_push(cx.waker().clone()) {
+                    // Without room in the queue, the task polls until ready.
+                    err.element().wake();
+                }
+            }
+            Poll::Pending
+        })
+    }
+
+    /// Wake every waiting task. This may be called in interrupt context.
+    pub fn wake_all(&self) {
+        let _guard = InterruptGuard::new();
+        for waker in self.waiters.lock().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Register `waker` to be woken once `expires` is reached. Returns whether a
+/// timer is armed.
+fn arm_sleeper(expires: u64, waker: &Waker) -> bool {
+    let _guard = InterruptGuard::new();
+    let mut sleepers = SLEEPERS.lock();
+    if sleepers.is_full() || time::add_timer(expires, wake_sleepers).is_err() {
+        return false;
+    }
+    sleepers.push((expires, waker.clone()));
+    true
+}
+
+fn wake_sleepers() {
+    let now = time::jiffies();
+    SLEEPERS.lock().retain(|(expires, waker)| {
+        let is_expired = *expires <= now;
+        if is_expired {
+            waker.wake_by_ref();
+        }
+        !is_expired
+    });
+}
+
+fn clone_waker(data: *const ()) -> RawWaker { RawWaker::new(data, &WAKER_VTABLE) }
+
+fn wake_task(data: *const ()) { READY.fetch_or(1 << data as usize, Ordering::Release); }
+
+fn drop_waker(_data: *const ()) {}
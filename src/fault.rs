@@ -0,0 +1,118 @@
+//! Fault injection for exercising error paths.
+//!
+//! A [`FaultSite`] is checked by a fallible operation before it does any
+//! work. With the `ktest` feature, a site can be set to fail every Nth call,
+//! either by tests or through the monitor `fail` command. Without the
+//! feature, a site never fails.
+
+#[cfg(feature = "ktest")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "ktest")]
+use arrayvec::ArrayVec;
+
+#[cfg(feature = "ktest")]
+use crate::interrupt::InterruptGuard;
+
+#[cfg(feature = "ktest")]
+const SITES_LEN: usize = 16;
+
+#[cfg(feature = "ktest")]
+static SITES: spin::Mutex<ArrayVec<&'static FaultSite, SITES_LEN>> =
+    spin::Mutex::new(ArrayVec::new_const());
+
+/// A named point where faults can be injected.
+pub struct FaultSite {
+    #[cfg(feature = "ktest")]
+    name: &'static str,
+    /// Fail every `interval`th call. 0 disables injection.
+    #[cfg(feature = "ktest")]
+    interval: AtomicU64,
+    #[cfg(feature = "ktest")]
+    call_cnt: AtomicU64,
+    #[cfg(feature = "ktest")]
+    fail_cnt: AtomicU64,
+}
+impl FaultSite {
+    #[allow(unused_variables)]
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            #[cfg(feature = "ktest")]
+            name,
+            #[cfg(feature = "ktest")]
+            interval: AtomicU64::new(0),
+            #[cfg(feature = "ktest")]
+            call_cnt: AtomicU64::new(0),
+            #[cfg(feature = "ktest")]
+            fail_cnt: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns whether the current call should fail.
+    #[inline]
+    pub fn should_fail(&self) -> bool {
+        #[cfg(feature = "ktest")]
+        {
+            let interval = self.interval.load(Ordering::Relaxed);
+            if interval == 0 {
+                return false;
+            }
+            let call_cnt = self.call_cnt.fetch_add(1, Ordering::Relaxed) + 1;
+            if call_cnt % interval != 0 {
+                return false;
+            }
+            self.fail_cnt.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        #[cfg(not(feature = "ktest"))]
+        false
+    }
+}
+#[cfg(feature = "ktest")]
+impl FaultSite {
+    pub fn name(&self) -> &'static str { self.name }
+
+    /// Fail every `interval`th call from now on. 0 disables injection.
+    pub fn set_interval(&self, interval: u64) {
+        self.call_cnt.store(0, Ordering::Relaxed);
+        self.interval.store(interval, Ordering::Relaxed);
+    }
+
+    pub fn interval(&self) -> u64 { self.interval.load(Ordering::Relaxed) }
+
+    /// Returns the number of injected failures.
+    pub fn fail_cnt(&self) -> u64 { self.fail_cnt.load(Ordering::Relaxed) }
+}
+
+/// Register `site` so that it can be found through [`find`] and
+/// [`for_each`].
+///
+/// # Panics
+/// Panics if the registry is full.
+#[allow(unused_variables)]
+pub fn register(site: &'static FaultSite) {
+    #[cfg(feature = "ktest")]
+    {
+        let _guard = InterruptGuard::new();
+        SITES
+            .lock()
+            .try_push(site)
+            .expect("fault site registry should not be full");
+    }
+}
+
+/// Returns the registered site named `name`.
+#[cfg(feature = "ktest")]
+pub fn find(name: &str) -> Option<&'static FaultSite> {
+    let _guard = InterruptGuard::new();
+    SITES.lock().iter().copied().find(|site| site.name == name)
+}
+
+/// Call `f` on every registered site in registration order.
+#[cfg(feature = "ktest")]
+pub fn for_each(mut f: impl FnMut(&FaultSite)) {
+    let _guard = InterruptGuard::new();
+    for site in SITES.lock().iter() {
+        f(site);
+    }
+}
@@ -0,0 +1,291 @@
+//! Flight recorder of significant kernel events.
+//!
+//! The last [`EVENTS_LEN`] events are kept in a ring in a fixed page of
+//! conventional memory, which the kernel never allocates and firmware leaves
+//! alone across a warm reset. A valid ring found at boot is reported, so a
+//! crash that took the console down can still be diagnosed after a reboot.
+//!
+//! Recording is lock-free and may be done from any context, including
+//! interrupt handlers and the panic handler. Sequence numbers keep counting
+//! across warm resets.
+
+use core::fmt;
+#[cfg(feature = "ktest")]
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use multiboot2::{BootInformation, MemoryAreaType};
+
+use crate::{klog, mem, time};
+
+/// Physical address of the recorder page, in conventional memory below the
+/// EBDA.
+const RECORDER_PADDR: usize = 0x9_0000;
+const RECORDER_SIZE: usize = 4096;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"KOEFLITE");
+const EVENTS_LEN: usize = (RECORDER_SIZE - size_of::<Header>()) / size_of::<Slot>();
+
+static RECORDER: spin::Once<&'static Recorder> = spin::Once::new();
+/// Whether [`record`] drops events, so tests injecting faults do not fill the
+/// ring kept across warm resets.
+#[cfg(feature = "ktest")]
+static IS_SUPPRESSED: AtomicBool = AtomicBool::new(false);
+
+/// Kind of a recorded event. The meaning of the event argument is given per
+/// kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum EventKind {
+    /// The kernel started. No argument.
+    Boot = 1,
+    /// The kernel panicked. No argument.
+    Panic = 2,
+    /// An unhandled page fault on the argument address.
+    PageFault = 3,
+    /// An unhandled general protection fault with the argument error code.
+    GeneralProtection = 4,
+    /// A double fault. No argument.
+    DoubleFault = 5,
+    /// Unclaimed interrupts keep arriving on the argument line.
+    IrqStorm = 6,
+    /// An allocation of the argument size failed.
+    AllocFail = 7,
+    /// A reboot was requested. No argument.
+    Reboot = 8,
+    /// A shutdown was requested. No argument.
+    Shutdown = 9,
+}
+impl EventKind {
+    const ALL: [EventKind; 9] = [
+        EventKind::Boot,
+        EventKind::Panic,
+        EventKind::PageFault,
+        EventKind::GeneralProtection,
+        EventKind::DoubleFault,
+        EventKind::IrqStorm,
+        EventKind::AllocFail,
+        EventKind::Reboot,
+        EventKind::Shutdown,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::Boot => "boot",
+            EventKind::Panic => "panic",
+            EventKind::PageFault => "page fault",
+            EventKind::GeneralProtection => "gp fault",
+            EventKind::DoubleFault => "double fault",
+            EventKind::IrqStorm => "irq storm",
+            EventKind::AllocFail => "alloc fail",
+            EventKind::Reboot => "reboot",
+            EventKind::Shutdown => "shutdown",
+        }
+    }
+
+    fn from_u32(value: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| *kind as u32 == value)
+    }
+}
+
+/// A recorded event.
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub seq: u64,
+    /// Count of boots since the recorder was set up, starting at 0.
+    pub boot: u32,
+    pub jiffies: u64,
+    pub kind: EventKind,
+    pub arg: u64,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} boot {} at {} jiffies: {} {:#x}",
+            self.seq,
+            self.boot,
+            self.jiffies,
+            self.kind.name(),
+            self.arg
+        )
+    }
+}
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+    next_seq: AtomicU64,
+    boot: AtomicU32,
+}
+
+/// A ring entry. Since every bit pattern is a valid slot, whatever is left
+/// in the page can be read safely.
+#[repr(C)]
+struct Slot {
+    /// Sequence number plus one, or 0 while the slot is empty or being
+    /// written.
+    seq: AtomicU64,
+    jiffies: AtomicU64,
+    /// Boot count in the high half, kind in the low half.
+    boot_kind: AtomicU64,
+    arg: AtomicU64,
+}
+impl Slot {
+    #[cfg(feature = "ktest")]
+    const fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            jiffies: AtomicU64::new(0),
+            boot_kind: AtomicU64::new(0),
+            arg: AtomicU64::new(0),
+        }
+    }
+
+    fn load(&self) -> Option<Event> {
+        let seq = self.seq.load(Ordering::Acquire);
+        let jiffies = self.jiffies.load(Ordering::Relaxed);
+        let boot_kind = self.boot_kind.load(Ordering::Relaxed);
+        let arg = self.arg.load(Ordering::Relaxed);
+        // The slot was rewritten while being read.
+        if seq == 0 || self.seq.load(Ordering::Acquire) != seq {
+            return None;
+        }
+        Some(Event {
+            seq: seq - 1,
+            boot: (boot_kind >> 32) as u32,
+            jiffies,
+            kind: EventKind::from_u32(boot_kind as u32)?,
+            arg,
+        })
+    }
+}
+
+/// A ring of events.
+#[repr(C)]
+pub struct Recorder {
+    header: Header,
+    slots: [Slot; EVENTS_LEN],
+}
+impl Recorder {
+    /// Creates an empty recorder, e.g. a scratch one for tests.
+    #[cfg(feature = "ktest")]
+    pub const fn new() -> Self {
+        Self {
+            header: Header {
+                magic: AtomicU64::new(MAGIC),
+                next_seq: AtomicU64::new(0),
+                boot: AtomicU32::new(0),
+            },
+            slots: [const { Slot::new() }; EVENTS_LEN],
+        }
+    }
+}
+const _: () = assert!(size_of::<Recorder>() <= RECORDER_SIZE);
+
+/// Set up the recorder page, reporting the events left by previous boots.
+///
+/// Recording is disabled if the page is not available memory.
+pub fn init(boot_info: &BootInformation) {
+    let recorder_range = RECORDER_PADDR..RECORDER_PADDR + RECORDER_SIZE;
+    let is_available = boot_info.memory_map_tag().is_some_and(|tag| {
+        tag.memory_areas().iter().any(|area| {
+            MemoryAreaType::from(area.typ()) == MemoryAreaType::Available
+                && area.start_address() as usize <= recorder_range.start
+                && recorder_range.end <= area.end_address() as usize
+        })
+    });
+    if !is_available {
+        klog!(
+            Warn,
+            "flight: recorder page unavailable"
+        );
+        return;
+    }
+
+    // SAFETY: Conventional memory lies in the kernel image mapping, and the
+    // page is never handed out by the frame allocator as it is below the
+    // kernel.
+    let recorder = unsafe { &*((mem::kernel_offset_vma() + RECORDER_PADDR) as *const Recorder) };
+    let header = &recorder.header;
+    if header.magic.load(Ordering::Relaxed) == MAGIC {
+        let boot = header.boot.load(Ordering::Relaxed);
+        let mut event_cnt = 0;
+        for_each_in(recorder, |event| {
+            klog!(Debug, "flight: {}", event);
+            event_cnt += 1;
+        });
+        klog!(
+            Info,
+            "flight: warm boot, {} events recorded by boot {}",
+            event_cnt,
+            boot
+        );
+        header.boot.store(boot.wrapping_add(1), Ordering::Relaxed);
+    } else {
+        for slot in &recorder.slots {
+            slot.seq.store(0, Ordering::Relaxed);
+        }
+        header.next_seq.store(0, Ordering::Relaxed);
+        header.boot.store(0, Ordering::Relaxed);
+        header.magic.store(MAGIC, Ordering::Release);
+    }
+
+    RECORDER.call_once(|| recorder);
+    record(EventKind::Boot, 0);
+}
+
+/// Record an event of `kind` with `arg`. Events before [`init`] are dropped.
+pub fn record(kind: EventKind, arg: u64) {
+    #[cfg(feature = "ktest")]
+    if IS_SUPPRESSED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(recorder) = RECORDER.get() {
+        record_in(recorder, kind, arg);
+    }
+}
+
+/// Drop events passed to [`record`] while `is_suppressed` is set.
+#[cfg(feature = "ktest")]
+pub fn set_suppressed(is_suppressed: bool) {
+    IS_SUPPRESSED.store(is_suppressed, Ordering::Relaxed);
+}
+
+/// Record an event of `kind` with `arg` in `recorder`.
+pub fn record_in(recorder: &Recorder, kind: EventKind, arg: u64) {
+    let header = &recorder.header;
+    let seq = header.next_seq.fetch_add(1, Ordering::Relaxed);
+    let boot = header.boot.load(Ordering::Relaxed);
+
+    let slot = &recorder.slots[seq as usize % EVENTS_LEN];
+    slot.seq.store(0, Ordering::Release);
+    slot.jiffies.store(time::jiffies(), Ordering::Relaxed);
+    slot.boot_kind.store(
+        (boot as u64) << 32 | kind as u64,
+        Ordering::Relaxed,
+    );
+    slot.arg.store(arg, Ordering::Relaxed);
+    slot.seq.store(seq + 1, Ordering::Release);
+}
+
+/// Call `f` on every recorded event from oldest to newest, including events
+/// of previous boots.
+pub fn for_each(f: impl FnMut(&Event)) {
+    if let Some(recorder) = RECORDER.get() {
+        for_each_in(recorder, f);
+    }
+}
+
+/// Call `f` on every event in `recorder` from oldest to newest.
+pub fn for_each_in(recorder: &Recorder, mut f: impl FnMut(&Event)) {
+    let next_seq = recorder.header.next_seq.load(Ordering::Relaxed);
+    let first_seq = next_seq.saturating_sub(EVENTS_LEN as u64);
+    for seq in first_seq..next_seq {
+        let slot = &recorder.slots[seq as usize % EVENTS_LEN];
+        if let Some(event) = slot.load().filter(|event| event.seq == seq) {
+            f(&event);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Global descriptor tables and task state segments.
+//!
+//! A static boot GDT is used until memory is initialized. Afterwards, every
+//! CPU loads its own GDT and TSS allocated through [`init`], so that
+//! per-CPU state such as the kernel entry stack is kept apart.
+
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::ops::Range;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use bitvec::field::BitField;
+use bitvec::order::Lsb0;
+use bitvec::view::BitView;
+
+use crate::common::Privilege;
+use crate::cpu::{self, MAX_CPUS};
+use crate::interrupt::InterruptGuard;
+use crate::phase::{self, Phase};
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+pub const USER_DATA_SELECTOR: u16 = 0x18 | Privilege::User as u16;
+pub const USER_CODE_SELECTOR: u16 = 0x20 | Privilege::User as u16;
+pub const TSS_SELECTOR: u16 = 0x28;
+
+static CPU_GDTS: [AtomicPtr<CpuGdt>; MAX_CPUS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_CPUS];
+
+/// Load the boot GDT, which only holds the kernel code segment.
+///
+/// This should be called before memory initialization, which unmaps the GDT
+/// loaded by the bootstrap code.
+pub fn init_boot() {
+    static mut BOOT_GDT: Gdt<2> = Gdt([SegmentDesc::invalid(), SegmentDesc::invalid()]);
+
+    // SAFETY: The boot GDT is only modified here, before it is loaded.
+    let gdt = unsafe { &mut *(&raw mut BOOT_GDT) };
+    gdt.0[1] = SegmentDesc::code(Privilege::Kernel);
+    // SAFETY: The boot GDT is static, and its code segment is at the same
+    // selector as the bootstrap GDT.
+    unsafe { load_gdt(gdt) };
+}
+
+/// Allocate and load the GDT and TSS of the current CPU.
+///
+/// This should be called once on every CPU, after memory is initialized.
+pub fn init() {
+    phase::require(Phase::Mem);
+    let cpu_id = cpu::current_id();
+    assert!(
+        cpu_id < MAX_CPUS,
+        "CPU id should be below MAX_CPUS"
+    );
+
+    let cpu_gdt = Box::leak(Box::new(CpuGdt {
+        gdt: Gdt([const { SegmentDesc::invalid() }; CPU_GDT_LEN]),
+        tss: Tss::new(),
+    }));
+    let tss_base = &raw const cpu_gdt.tss as u64;
+    let [tss_low, tss_high] = SegmentDesc::tss(tss_base);
+    cpu_gdt.gdt.0 = [
+        SegmentDesc::invalid(),
+        SegmentDesc::code(Privilege::Kernel),
+        SegmentDesc::data(Privilege::Kernel),
+        SegmentDesc::data(Privilege::User),
+        SegmentDesc::code(Privilege::User),
+        tss_low,
+        tss_high,
+    ];
+
+    let prev = CPU_GDTS[cpu_id].swap(cpu_gdt, Ordering::AcqRel);
+    assert!(
+        prev.is_null(),
+        "gdt::init should be called once per CPU"
+    );
+
+    // SAFETY: The CPU GDT is leaked, and all selectors are set up above.
+    unsafe {
+        load_gdt(&cpu_gdt.gdt);
+        asm!(
+            "push {code}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            "mov ss, {data:x}",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "ltr {tss:x}",
+            code = in(reg) KERNEL_CODE_SELECTOR as u64,
+            data = in(reg) KERNEL_DATA_SELECTOR,
+            tss = in(reg) TSS_SELECTOR,
+            tmp = out(reg) _,
+        );
+    }
+}
+
+/// Set the stack loaded on entry to the kernel from user mode on the current
+/// CPU.
+///
+/// # Panics
+/// Panics if [`init`] has not been called on the current CPU.
+pub fn set_kernel_entry_stack(stack_top: usize) {
+    let _guard = InterruptGuard::new();
+    let cpu_gdt = current_cpu_gdt();
+    // SAFETY: The TSS of the current CPU is only accessed by the current CPU,
+    // which has interrupt disabled.
+    unsafe { (&raw mut (*cpu_gdt).tss.rsp[0]).write_unaligned(stack_top as u64) };
+}
+
+/// Set the stack loaded on entry to an interrupt with IST index `idx` on the
+/// current CPU.
+///
+/// # Panics
+/// Panics if `idx` is not in `1..=7`, or [`init`] has not been called on the
+/// current CPU.
+pub fn set_interrupt_stack(idx: u8, stack_top: usize) {
+    assert!(
+        (1..=7).contains(&idx),
+        "IST index should be in 1..=7"
+    );
+    let _guard = InterruptGuard::new();
+    let cpu_gdt = current_cpu_gdt();
+    // SAFETY: See set_kernel_entry_stack.
+    unsafe { (&raw mut (*cpu_gdt).tss.ist[idx as usize - 1]).write_unaligned(stack_top as u64) };
+}
+
+/// Check that the GDT and task register of the current CPU match the
+/// selector constants.
+///
+/// # Panics
+/// Panics on the first mismatch, or if [`init`] has not been called on the
+/// current CPU.
+pub fn self_check() {
+    let _guard = InterruptGuard::new();
+    // SAFETY: The GDT of the current CPU is leaked and only modified by init.
+    let gdt = unsafe { &(*current_cpu_gdt()).gdt.0 };
+    let desc = |selector: u16| &gdt[selector as usize / size_of::<SegmentDesc>()];
+
+    let kernel_code = desc(KERNEL_CODE_SELECTOR);
+    assert!(
+        kernel_code.is_code() && kernel_code.dpl() == Privilege::Kernel as u8,
+        "KERNEL_CODE_SELECTOR should select a kernel code segment"
+    );
+    let kernel_data = desc(KERNEL_DATA_SELECTOR);
+    assert!(
+        kernel_data.is_data() && kernel_data.dpl() == Privilege::Kernel as u8,
+        "KERNEL_DATA_SELECTOR should select a kernel data segment"
+    );
+    let user_data = desc(USER_DATA_SELECTOR);
+    assert!(
+        user_data.is_data() && user_data.dpl() == Privilege::User as u8,
+        "USER_DATA_SELECTOR should select a user data segment"
+    );
+    let user_code = desc(USER_CODE_SELECTOR);
+    assert!(
+        user_code.is_code() && user_code.dpl() == Privilege::User as u8,
+        "USER_CODE_SELECTOR should select a user code segment"
+    );
+    assert!(
+        desc(TSS_SELECTOR).is_tss(),
+        "TSS_SELECTOR should select a TSS"
+    );
+    assert!(
+        USER_CODE_SELECTOR == USER_DATA_SELECTOR + 8,
+        "sysret expects the user code segment right after the user data segment"
+    );
+
+    let cs: u16;
+    let tr: u16;
+    // SAFETY: Reading segment registers has no side effect.
+    unsafe { asm!("mov {:x}, cs", "str {:x}", out(reg) cs, out(reg) tr) };
+    assert!(
+        cs == KERNEL_CODE_SELECTOR,
+        "kernel should run on KERNEL_CODE_SELECTOR"
+    );
+    assert!(
+        tr == TSS_SELECTOR,
+        "task register should hold TSS_SELECTOR"
+    );
+}
+
+fn current_cpu_gdt() -> *mut CpuGdt {
+    let cpu_gdt = CPU_GDTS[cpu::current_id()].load(Ordering::Acquire);
+    assert!(
+        !cpu_gdt.is_null(),
+        "gdt should be initialized on current CPU"
+    );
+    cpu_gdt
+}
+
+/// Load `gdt` into GDTR.
+///
+/// # Safety
+/// `gdt` should stay alive while loaded, and hold the segments currently in
+/// use at the same selectors.
+unsafe fn load_gdt<const N: usize>(gdt: &Gdt<N>) {
+    let gdtr = Gdtr {
+        limit: (N * size_of::<SegmentDesc>() - 1) as u16,
+        base: ptr::from_ref(gdt).cast(),
+    };
+
+    unsafe {
+        asm!(
+            "lgdt [{gdtr}]",
+            gdtr = in(reg) &gdtr as *const Gdtr
+        )
+    };
+}
+
+const CPU_GDT_LEN: usize = 7;
+
+#[repr(C)]
+struct CpuGdt {
+    gdt: Gdt<CPU_GDT_LEN>,
+    tss: Tss,
+}
+
+#[repr(C, packed(2))]
+struct Gdtr {
+    limit: u16,
+    base: *const SegmentDesc,
+}
+
+#[repr(C, align(8))]
+struct Gdt<const N: usize>([SegmentDesc; N]);
+
+#[repr(C, packed(4))]
+struct Tss {
+    _reserved0: u32,
+    rsp: [u64; 3],
+    _reserved1: u64,
+    ist: [u64; 7],
+    _reserved2: u64,
+    _reserved3: u16,
+    iomap_base: u16,
+}
+impl Tss {
+    const fn new() -> Self {
+        Self {
+            _reserved0: 0,
+            rsp: [0; 3],
+            _reserved1: 0,
+            ist: [0; 7],
+            _reserved2: 0,
+            _reserved3: 0,
+            // No I/O permission bitmap
+            iomap_base: size_of::<Self>() as u16,
+        }
+    }
+}
+
+#[repr(C, packed)]
+struct SegmentDesc(u64);
+impl SegmentDesc {
+    const BASE_HIGH_IDXS: Range<usize> = 56..64;
+    const BASE_LOW_IDXS: Range<usize> = 16..40;
+    const DEFAULT_IDXS: Range<usize> = 54..55;
+    const DESC_TYPE_IDXS: Range<usize> = 44..45;
+    const DPL_IDXS: Range<usize> = 45..47;
+    const GRANULARITY_IDXS: Range<usize> = 55..56;
+    const LIMIT_LOW_IDXS: Range<usize> = 0..16;
+    const LONG_MODE_IDXS: Range<usize> = 53..54;
+    const P_IDXS: Range<usize> = 47..48;
+    const TYPE_IDXS: Range<usize> = 40..44;
+
+    fn code(dpl: Privilege) -> Self {
+        let mut bits = 0u64;
+        let view = bits.view_bits_mut::<Lsb0>();
+        view[Self::TYPE_IDXS].store_le(0b1010);
+        view[Self::DESC_TYPE_IDXS].store_le(1);
+        view[Self::DPL_IDXS].store_le(dpl as u8);
+        view[Self::P_IDXS].store_le(1);
+        view[Self::LONG_MODE_IDXS].store(1);
+        Self(bits)
+    }
+
+    fn data(dpl: Privilege) -> Self {
+        let mut bits = 0u64;
+        let view = bits.view_bits_mut::<Lsb0>();
+        view[Self::TYPE_IDXS].store_le(0b0010);
+        view[Self::DESC_TYPE_IDXS].store_le(1);
+        view[Self::DPL_IDXS].store_le(dpl as u8);
+        view[Self::P_IDXS].store_le(1);
+        Self(bits)
+    }
+
+    /// Returns the low and high descriptors of an available 64-bit TSS at
+    /// `base`.
+    fn tss(base: u64) -> [Self; 2] {
+        let mut bits = 0u64;
+        let view = bits.view_bits_mut::<Lsb0>();
+        view[Self::LIMIT_LOW_IDXS].store_le(size_of::<Tss>() - 1);
+        view[Self::BASE_LOW_IDXS].store_le(base & 0xFF_FFFF);
+        view[Self::TYPE_IDXS].store_le(0b1001);
+        view[Self::DPL_IDXS].store_le(Privilege::Kernel as u8);
+        view[Self::P_IDXS].store_le(1);
+        view[Self::BASE_HIGH_IDXS].store_le((base >> 24) & 0xFF);
+        [Self(bits), Self(base >> 32)]
+    }
+
+    const fn invalid() -> Self { Self(0) }
+
+    /// Returns whether `self` is a present 64-bit code segment.
+    fn is_code(&self) -> bool {
+        let bits = self.0;
+        let view = bits.view_bits::<Lsb0>();
+        self.is_present_segment()
+            && view[Self::TYPE_IDXS].load_le::<u8>() & 0b1000 != 0
+            && view[Self::LONG_MODE_IDXS].load_le::<u8>() == 1
+    }
+
+    /// Returns whether `self` is a present data segment.
+    fn is_data(&self) -> bool {
+        let bits = self.0;
+        self.is_present_segment()
+            && bits.view_bits::<Lsb0>()[Self::TYPE_IDXS].load_le::<u8>() & 0b1000 == 0
+    }
+
+    /// Returns whether `self` is the low descriptor of a present TSS.
+    fn is_tss(&self) -> bool {
+        let bits = self.0;
+        let view = bits.view_bits::<Lsb0>();
+        view[Self::P_IDXS].load_le::<u8>() == 1
+            && view[Self::DESC_TYPE_IDXS].load_le::<u8>() == 0
+            && view[Self::TYPE_IDXS].load_le::<u8>() & 0b1101 == 0b1001
+    }
+
+    fn is_present_segment(&self) -> bool {
+        let bits = self.0;
+        let view = bits.view_bits::<Lsb0>();
+        view[Self::P_IDXS].load_le::<u8>() == 1 && view[Self::DESC_TYPE_IDXS].load_le::<u8>() == 1
+    }
+
+    fn dpl(&self) -> u8 {
+        let bits = self.0;
+        bits.view_bits::<Lsb0>()[Self::DPL_IDXS].load_le()
+    }
+}
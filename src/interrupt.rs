@@ -1,28 +1,43 @@
 use core::arch::{asm, global_asm};
 use core::cell::SyncUnsafeCell;
 use core::ops::Range;
-use core::sync::atomic::{self, AtomicUsize};
-use core::{array, ptr};
+use core::sync::atomic::{self, AtomicBool, AtomicUsize};
+use core::{array, fmt, ptr};
 
 use bitvec::field::BitField;
 use bitvec::order::Lsb0;
 use bitvec::view::BitView;
 use handler::{exception_handler, ISR_TABLE};
+pub use irq::{
+    irq_line_stats, is_irq_masked, is_woken, mask_irq, register_irq, resume_irqs, suspend_irqs,
+    unmask_irq, IrqReturn, IRQ_LINE_CNT,
+};
 use pic::init_pic;
 use spin::Mutex;
 
-use crate::common::{hlt, Privilege};
+use crate::common::{hlt, stack, KiB, Privilege};
+use crate::phase::{self, Phase};
+use crate::{gdt, stats};
 
+mod extable;
 mod handler;
+mod irq;
 mod pic;
 
 /// An RAII implementation of reentrant interrupt lock. This structure
 /// guarentees that interrupt is disabled.
+///
+/// Interrupt is re-enabled when the outermost guard is dropped, only if it was
+/// enabled when the outermost guard was created.
 pub struct InterruptGuard();
 impl InterruptGuard {
     pub fn new() -> Self {
+        let was_enabled = is_interrupt_enabled();
         disable_interrupt();
-        INTERRUPT_GUARD_CNT.fetch_add(1, atomic::Ordering::Relaxed);
+        let prev_cnt = INTERRUPT_GUARD_CNT.fetch_add(1, atomic::Ordering::Relaxed);
+        if prev_cnt == 0 {
+            INTERRUPT_WAS_ENABLED.store(was_enabled, atomic::Ordering::Relaxed);
+        }
         Self()
     }
 }
@@ -30,28 +45,76 @@ impl InterruptGuard {
 impl Drop for InterruptGuard {
     fn drop(&mut self) {
         let prev_cnt = INTERRUPT_GUARD_CNT.fetch_sub(1, atomic::Ordering::Relaxed);
-        if prev_cnt == 1 {
+        if prev_cnt == 1 && INTERRUPT_WAS_ENABLED.load(atomic::Ordering::Relaxed) {
             enable_interrupt();
         }
     }
 }
 static INTERRUPT_GUARD_CNT: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPT_WAS_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Nesting depth of interrupt and exception handlers.
+static HANDLER_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether an interrupt or exception handler is running.
+pub fn in_interrupt() -> bool { HANDLER_DEPTH.load(atomic::Ordering::Relaxed) != 0 }
 
-pub type IrqHandler = fn();
+/// Release `guard` and halt until the next interrupt.
+///
+/// Interrupt is enabled only right before halting, so an interrupt arriving
+/// after a condition is checked under `guard` still wakes the CPU.
+///
+/// # Panics
+/// Panics if `guard` is not the outermost guard, or interrupt was disabled
+/// when it was created.
+pub fn wait_for_interrupt(guard: InterruptGuard) {
+    assert!(
+        INTERRUPT_GUARD_CNT.load(atomic::Ordering::Relaxed) == 1
+            && INTERRUPT_WAS_ENABLED.load(atomic::Ordering::Relaxed),
+        "waiting for interrupt should re-enable interrupt"
+    );
+    INTERRUPT_GUARD_CNT.store(0, atomic::Ordering::Relaxed);
+    core::mem::forget(guard);
+    // SAFETY: sti takes effect after the next instruction, so no interrupt
+    // is taken before hlt.
+    unsafe { asm!("sti", "hlt") };
+}
 
 // x86-64 stuff
 
 pub fn init() {
+    phase::enter(Phase::Interrupt);
+    stats::register(&irq::IRQ_STAT);
+    stats::register(&irq::SPURIOUS_IRQ_STAT);
+    stats::register(&handler::PAGE_FAULT_STAT);
+
     init_idtr();
+    init_fault_stacks();
     init_exn_handlers();
     init_irq_handlers();
     init_pic();
 
     pic::mask_all();
-    pic::unmask(1);
     enable_interrupt();
 }
 
+/// Check that every present IDT gate enters the kernel code segment.
+///
+/// # Panics
+/// Panics on the first gate with another selector.
+pub fn self_check() {
+    let idt = IDT_HANDLE.lock();
+    for (vector, desc) in idt.0.iter().enumerate() {
+        let attributes = desc.attributes;
+        let segment_selector = desc.segment_selector;
+        let is_present = attributes.view_bits::<Lsb0>()[InterruptDesc::P_IDXS].load_le::<u8>() == 1;
+        assert!(
+            !is_present || segment_selector == gdt::KERNEL_CODE_SELECTOR,
+            "interrupt gate {} should use KERNEL_CODE_SELECTOR",
+            vector
+        );
+    }
+}
+
 fn enable_interrupt() {
     unsafe {
         asm!("sti");
@@ -64,6 +127,20 @@ fn disable_interrupt() {
     };
 }
 
+fn is_interrupt_enabled() -> bool {
+    const FLAGS_IF: usize = 1 << 9;
+
+    let flags: usize;
+    unsafe {
+        asm!(
+            "pushfq",
+            "pop {flags}",
+            flags = out(reg) flags,
+        )
+    };
+    flags & FLAGS_IF != 0
+}
+
 fn init_idtr() {
     let idtr = Idtr {
         limit: (Idt::LEN * size_of::<InterruptDesc>()) as u16,
@@ -78,6 +155,28 @@ fn init_idtr() {
     };
 }
 
+/// IST index of the stack used by the double fault handler, so that a kernel
+/// stack overflow can still be reported.
+const DF_IST: u8 = 1;
+const FAULT_STACK_SIZE: usize = 16 * KiB;
+
+#[repr(C, align(16))]
+struct FaultStack([u8; FAULT_STACK_SIZE]);
+static DF_STACK: SyncUnsafeCell<FaultStack> =
+    SyncUnsafeCell::new(FaultStack([0; FAULT_STACK_SIZE]));
+
+fn init_fault_stacks() {
+    // SAFETY: The stack is not used until it is set in the TSS.
+    unsafe { stack::paint(df_stack()) };
+    gdt::set_interrupt_stack(DF_IST, df_stack().end);
+}
+
+/// Returns the range of the double fault stack.
+pub fn df_stack() -> Range<usize> {
+    let bottom = DF_STACK.get() as usize;
+    bottom..bottom + FAULT_STACK_SIZE
+}
+
 fn init_exn_handlers() {
     let mut idt = IDT_HANDLE.lock();
 
@@ -88,6 +187,7 @@ fn init_exn_handlers() {
         }
         idt.0[i] = InterruptDesc::exn(addr);
     }
+    idt.0[VECTOR_DF as usize].set_ist(DF_IST);
 }
 
 fn init_irq_handlers() {
@@ -138,7 +238,7 @@ enum GateTyp {
 
 impl InterruptDesc {
     const DPL_IDXS: Range<usize> = 13..15;
-    const IST_IDXS: Range<usize> = 0..2;
+    const IST_IDXS: Range<usize> = 0..3;
     const P_IDXS: Range<usize> = 15..16;
     const TYPE_IDXS: Range<usize> = 8..12;
 
@@ -150,11 +250,9 @@ impl InterruptDesc {
         let high_low_offset = addr_bits[16..32].load_le();
         let high_offset = addr_bits[32..64].load_le();
 
-        // NOTE: The CS segment selector should be 8.
-        let segment_selector = 8;
+        let segment_selector = gdt::KERNEL_CODE_SELECTOR;
         let _reserved = 0;
 
-        // TODO: Implement interrupt stack table
         let mut attributes = 0;
         let attributes_bits = attributes.view_bits_mut::<Lsb0>();
         attributes_bits[Self::TYPE_IDXS].store_le(typ as u8);
@@ -170,6 +268,12 @@ impl InterruptDesc {
             _reserved,
         }
     }
+    /// Switch to the stack at IST index `ist` on entry.
+    fn set_ist(&mut self, ist: u8) {
+        let mut attributes = self.attributes;
+        attributes.view_bits_mut::<Lsb0>()[Self::IST_IDXS].store_le(ist);
+        self.attributes = attributes;
+    }
     const fn null() -> Self {
         Self {
             low_low_offset: 0,
@@ -210,12 +314,79 @@ const VECTOR_CP: InterruptVector = 21;
 
 const VECTOR_PIC: InterruptVector = 32;
 
+/// Register state saved on every kernel entry through the IDT.
+///
+/// The general purpose registers are pushed by the common trap stub in
+/// `handler.S`, followed by the vector and error code pushed by the entry
+/// stub, and the frame pushed by the CPU. Changes made to the frame by a
+/// handler are restored on return.
 #[repr(C)]
-struct InterruptStack {
-    errno: usize,
-    ip: usize,
-    cs: usize,
-    flags: usize,
-    sp: usize,
-    ss: usize,
+#[derive(Debug, Clone)]
+pub struct TrapFrame {
+    pub r15: usize,
+    pub r14: usize,
+    pub r13: usize,
+    pub r12: usize,
+    pub r11: usize,
+    pub r10: usize,
+    pub r9: usize,
+    pub r8: usize,
+    pub rbp: usize,
+    pub rdi: usize,
+    pub rsi: usize,
+    pub rdx: usize,
+    pub rcx: usize,
+    pub rbx: usize,
+    pub rax: usize,
+    pub vector: usize,
+    /// Error code pushed by the CPU, or 0 for vectors without one.
+    pub errno: usize,
+    pub ip: usize,
+    pub cs: usize,
+    pub flags: usize,
+    pub sp: usize,
+    pub ss: usize,
+}
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regs = [
+            ("rax", self.rax),
+            ("rbx", self.rbx),
+            ("rcx", self.rcx),
+            ("rdx", self.rdx),
+            ("rsi", self.rsi),
+            ("rdi", self.rdi),
+            ("rbp", self.rbp),
+            ("rsp", self.sp),
+            ("r8", self.r8),
+            ("r9", self.r9),
+            ("r10", self.r10),
+            ("r11", self.r11),
+            ("r12", self.r12),
+            ("r13", self.r13),
+            ("r14", self.r14),
+            ("r15", self.r15),
+            ("rip", self.ip),
+            ("rfl", self.flags),
+        ];
+        for (i, (name, val)) in regs.iter().enumerate() {
+            let sep = if i % 3 == 2 { "\n" } else { " " };
+            write!(f, "{:>3}={:016x}{}", name, val, sep)?;
+        }
+        writeln!(
+            f,
+            "vec={} err={:#x} cs={:#x} ss={:#x}",
+            self.vector, self.errno, self.cs, self.ss
+        )
+    }
+}
+impl TrapFrame {
+    /// Returns the privilege level the trap was taken from.
+    pub fn privilege(&self) -> Privilege {
+        if self.cs & 0b11 == 0 {
+            Privilege::Kernel
+        } else {
+            Privilege::User
+        }
+    }
 }
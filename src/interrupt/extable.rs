@@ -0,0 +1,49 @@
+//! Exception fixup table.
+//!
+//! Code that may fault on purpose, such as user memory accessors, records the
+//! address of each faulting instruction with the address to resume at in the
+//! `.ex_table` section:
+//!
+//! ```text
+//! 1:  rep movsb
+//! 2:
+//!     .pushsection .ex_table, "a"
+//!     .balign 8
+//!     .quad 1b, 2b
+//!     .popsection
+//! ```
+//!
+//! Page faults and general protection faults at a recorded instruction resume
+//! at its fixup instead of being treated as kernel bugs.
+
+use core::slice;
+
+use super::TrapFrame;
+use crate::mem::layout;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ExTableEntry {
+    ip: usize,
+    fixup: usize,
+}
+
+/// Resume `frame` at the fixup of its faulting instruction.
+///
+/// Returns `None` if the instruction has no fixup.
+pub(super) fn fixup(frame: &mut TrapFrame) -> Option<()> {
+    let entry = entries().iter().find(|entry| entry.ip == frame.ip)?;
+    frame.ip = entry.fixup;
+    Some(())
+}
+
+fn entries() -> &'static [ExTableEntry] {
+    let table = layout::ex_table();
+    // SAFETY: The section is filled only with 8 byte aligned entries.
+    unsafe {
+        slice::from_raw_parts(
+            table.start().into_ptr::<ExTableEntry>(),
+            table.size / size_of::<ExTableEntry>(),
+        )
+    }
+}
@@ -1,49 +1,156 @@
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
 use core::cell::SyncUnsafeCell;
 use core::fmt::Write as _;
 use core::mem::MaybeUninit;
 use core::ptr;
+use core::sync::atomic::Ordering;
 
 use super::pic::ack;
-use super::{InterruptStack, InterruptVector, VECTOR_DF, VECTOR_PF, VECTOR_PIC};
+use super::{
+    extable, irq, InterruptVector, TrapFrame, HANDLER_DEPTH, VECTOR_DB, VECTOR_DF, VECTOR_GP,
+    VECTOR_PF, VECTOR_PIC,
+};
 use crate::common::hlt;
-use crate::drivers::ps2;
 use crate::drivers::vga::VGA_BUFFER;
-use crate::log;
+use crate::flight::{self, EventKind};
+use crate::stats::{self, Stat};
+use crate::{boot, debug, log};
 
 
 #[repr(transparent)]
 #[derive(Clone, Copy)]
 struct Isr(pub extern "C" fn());
 
-fn page_fault_handler(stack: &InterruptStack) {
-    log!("Page Fault!");
-    hlt();
+/// Marks a handler as running for [`in_interrupt`][super::in_interrupt]
+/// until dropped.
+struct HandlerScope;
+impl HandlerScope {
+    fn enter() -> Self {
+        HANDLER_DEPTH.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+impl Drop for HandlerScope {
+    fn drop(&mut self) { HANDLER_DEPTH.fetch_sub(1, Ordering::Relaxed); }
+}
+
+pub(super) static PAGE_FAULT_STAT: Stat = Stat::counter("interrupt.page_faults");
+
+fn page_fault_handler(frame: &mut TrapFrame) {
+    PAGE_FAULT_STAT.inc();
+    if extable::fixup(frame).is_some() {
+        return;
+    }
+
+    const ERR_PRESENT: usize = 1 << 0;
+    const ERR_WRITE: usize = 1 << 1;
+    const ERR_USER: usize = 1 << 2;
+    const ERR_FETCH: usize = 1 << 4;
+
+    let addr: usize;
+    unsafe { asm!("mov {}, cr2", out(reg) addr) };
+    let err = frame.errno;
+    let cause = match err & ERR_PRESENT {
+        0 => "non-present",
+        _ => "protection violation on",
+    };
+    let access = match err & (ERR_FETCH | ERR_WRITE) {
+        0 => "read",
+        ERR_WRITE => "write",
+        _ => "fetch",
+    };
+    let mode = match err & ERR_USER {
+        0 => "kernel",
+        _ => "user",
+    };
+    flight::record(EventKind::PageFault, addr as u64);
+    log!(
+        "Page Fault! {} {} of {:#x} from {} mode\n{}",
+        cause,
+        access,
+        addr,
+        mode,
+        frame
+    );
+    stats::for_each(|stat| {
+        if stat.name().starts_with("mem.") {
+            log!("{} {}\n", stat.name(), stat.get());
+        }
+    });
+    flush_and_hlt();
 }
 
-fn double_fault_handler(stack: &InterruptStack) {
-    log!("Double Fault!");
-    hlt();
+fn general_protection_handler(frame: &mut TrapFrame) {
+    if extable::fixup(frame).is_some() {
+        return;
+    }
+    flight::record(
+        EventKind::GeneralProtection,
+        frame.errno as u64,
+    );
+    log!("General Protection Fault!\n{}", frame);
+    flush_and_hlt();
+}
+
+fn double_fault_handler(frame: &mut TrapFrame) {
+    const STACK_DUMP_LEN: usize = 8;
+
+    // SAFETY: The kernel does not return from a double fault, so the console
+    // will not be used again by the interrupted code.
+    unsafe { VGA_BUFFER.force_unlock() };
+    flight::record(EventKind::DoubleFault, 0);
+    log!("Double Fault!\n{}", frame);
+
+    let stack = boot::boot_stack();
+    if !stack.contains(&frame.sp) {
+        log!("stack pointer outside of known stacks\n");
+        flush_and_hlt();
+    }
+    log!(
+        "boot stack {:#x}..{:#x}\n",
+        stack.start,
+        stack.end
+    );
+
+    let dump_end = stack
+        .end
+        .min(frame.sp + STACK_DUMP_LEN * size_of::<usize>());
+    for addr in (frame.sp..dump_end).step_by(size_of::<usize>()) {
+        // SAFETY: addr is within the boot stack, which is always mapped.
+        let val = unsafe { ptr::read_volatile(addr as *const usize) };
+        log!("{:016x}: {:016x}\n", addr, val);
+    }
+    flush_and_hlt();
+}
+
+/// Show the report and halt.
+///
+/// The console is only refreshed by the timer, which does not run if the
+/// fault was taken with interrupt disabled.
+fn flush_and_hlt() -> ! {
+    VGA_BUFFER.lock().flush();
+    hlt()
 }
 
 fn default_exn_handler() {}
 
 #[no_mangle]
-pub extern "C" fn exception_handler(vec: InterruptVector, stack: &InterruptStack) {
-    match vec {
-        VECTOR_PF => page_fault_handler(stack),
-        VECTOR_DF => double_fault_handler(stack),
+pub extern "C" fn exception_handler(frame: &mut TrapFrame) {
+    let _scope = HandlerScope::enter();
+    match frame.vector as InterruptVector {
+        VECTOR_DB => debug::handle_debug_exception(frame),
+        VECTOR_GP => general_protection_handler(frame),
+        VECTOR_PF => page_fault_handler(frame),
+        VECTOR_DF => double_fault_handler(frame),
         _ => default_exn_handler(),
     }
 }
 
 #[no_mangle]
-pub extern "C" fn irq_handler(vec: InterruptVector, stack: &InterruptStack) {
-    let irq = vec - VECTOR_PIC;
-    match irq {
-        1 => ps2::ps2_keyboard_handler(),
-        _ => (),
-    }
+pub extern "C" fn irq_handler(frame: &mut TrapFrame) {
+    let _scope = HandlerScope::enter();
+    let irq = frame.vector as InterruptVector - VECTOR_PIC;
+    irq::dispatch(irq);
     ack(irq);
 }
 // x86-64 stuff
@@ -0,0 +1,241 @@
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering};
+
+use arrayvec::ArrayVec;
+
+use super::{pic, InterruptGuard};
+use crate::error::{KError, KResult};
+use crate::flight::{self, EventKind};
+use crate::phase::{self, Phase};
+use crate::random;
+use crate::stats::Stat;
+
+/// Number of legacy IRQ lines routed through the PIC.
+pub const IRQ_LINE_CNT: usize = 16;
+/// Maximum number of handlers sharing a single IRQ line.
+pub const IRQ_LINE_HANDLERS_LEN: usize = 4;
+
+/// Result of an [`IrqHandler`] servicing an IRQ line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// The interrupt was raised by the handler's device and is serviced.
+    Handled,
+    /// The interrupt was not raised by the handler's device.
+    NotMine,
+}
+
+/// A handler for an IRQ line. Since legacy lines may be shared, a handler
+/// should check its device before claiming the interrupt.
+pub type IrqHandler = fn() -> IrqReturn;
+
+struct IrqAction {
+    handler: IrqHandler,
+    claim_cnt: usize,
+}
+
+struct IrqLine {
+    actions: spin::Mutex<ArrayVec<IrqAction, IRQ_LINE_HANDLERS_LEN>>,
+    spurious_cnt: AtomicUsize,
+}
+impl IrqLine {
+    const fn new() -> Self {
+        Self {
+            actions: spin::Mutex::new(ArrayVec::new_const()),
+            spurious_cnt: AtomicUsize::new(0),
+        }
+    }
+}
+
+static IRQ_LINES: [IrqLine; IRQ_LINE_CNT] = [const { IrqLine::new() }; IRQ_LINE_CNT];
+/// Number of outstanding [`unmask_irq`] calls on each line. A line is
+/// unmasked at the controller iff its count is nonzero.
+static UNMASK_CNTS: spin::Mutex<[usize; IRQ_LINE_CNT]> = spin::Mutex::new([0; IRQ_LINE_CNT]);
+
+/// Line through which the secondary PIC is chained to the primary PIC.
+const CASCADE_IRQ: u8 = 2;
+
+/// Unclaimed interrupts on a line between two recorded storm events.
+const IRQ_STORM_SPURIOUS: usize = 1000;
+
+/// Lines left unmasked while suspended, as a bitmap.
+static WAKE_LINES: AtomicU16 = AtomicU16::new(0);
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+pub(super) static IRQ_STAT: Stat = Stat::counter("interrupt.irqs");
+pub(super) static SPURIOUS_IRQ_STAT: Stat = Stat::counter("interrupt.spurious_irqs");
+
+/// Claim statistics of an IRQ line.
+#[derive(Debug, Clone)]
+pub struct IrqLineStats {
+    /// Number of interrupts claimed by each handler, in registration order.
+    pub claim_cnts: ArrayVec<(IrqHandler, usize), IRQ_LINE_HANDLERS_LEN>,
+    /// Number of interrupts which no handler claimed.
+    pub spurious_cnt: usize,
+}
+
+/// Register `handler` on `irq`. Handlers on a shared line are called in
+/// registration order.
+///
+/// Fails with [`KError::Inval`] if `irq` is not a valid line, or
+/// [`KError::NoSpc`] if the line is full.
+pub fn register_irq(irq: u8, handler: IrqHandler) -> KResult<()> {
+    phase::require(Phase::Interrupt);
+    let line = IRQ_LINES.get(irq as usize).ok_or(KError::Inval)?;
+
+    let _guard = InterruptGuard::new();
+    line.actions
+        .lock()
+        .try_push(IrqAction {
+            handler,
+            claim_cnt: 0,
+        })
+        .map_err(|_| KError::NoSpc)
+}
+
+/// Take a reference on `irq` being unmasked, unmasking it at the interrupt
+/// controller if it was masked.
+///
+/// Fails with [`KError::Inval`] if `irq` is not a valid line.
+pub fn unmask_irq(irq: u8) -> KResult<()> {
+    phase::require(Phase::Interrupt);
+    if irq as usize >= IRQ_LINE_CNT {
+        return Err(KError::Inval);
+    }
+
+    let _guard = InterruptGuard::new();
+    let mut cnts = UNMASK_CNTS.lock();
+    unmask_line(&mut cnts, irq);
+    Ok(())
+}
+
+/// Drop a reference taken by [`unmask_irq`], masking `irq` at the interrupt
+/// controller once no reference is left.
+///
+/// Fails with [`KError::Inval`] if `irq` is not a valid line or is not
+/// unmasked.
+pub fn mask_irq(irq: u8) -> KResult<()> {
+    let _guard = InterruptGuard::new();
+    let mut cnts = UNMASK_CNTS.lock();
+    if cnts.get(irq as usize).is_none_or(|cnt| *cnt == 0) {
+        return Err(KError::Inval);
+    }
+    mask_line(&mut cnts, irq);
+    Ok(())
+}
+
+/// Returns whether `irq` is masked, or `None` if `irq` is not a valid line.
+pub fn is_irq_masked(irq: u8) -> Option<bool> {
+    let _guard = InterruptGuard::new();
+    UNMASK_CNTS.lock().get(irq as usize).map(|cnt| *cnt == 0)
+}
+
+/// Mask every line at the interrupt controller except the `wake` lines, for
+/// suspending. Unmask references are kept, and lines are restored by
+/// [`resume_irqs`].
+///
+/// Fails with [`KError::Inval`] if a wake line is not valid or not unmasked.
+pub fn suspend_irqs(wake: &[u8]) -> KResult<()> {
+    let _guard = InterruptGuard::new();
+    let cnts = UNMASK_CNTS.lock();
+    let mut wake_lines = 0u16;
+    for &irq in wake {
+        if cnts.get(irq as usize).is_none_or(|cnt| *cnt == 0) {
+            return Err(KError::Inval);
+        }
+        wake_lines |= 1 << irq;
+        if irq >= 8 {
+            wake_lines |= 1 << CASCADE_IRQ;
+        }
+    }
+
+    WOKEN.store(false, Ordering::Relaxed);
+    WAKE_LINES.store(wake_lines, Ordering::Relaxed);
+    for irq in 0..IRQ_LINE_CNT as u8 {
+        if wake_lines & (1 << irq) == 0 && cnts[irq as usize] != 0 {
+            pic::mask(irq);
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether a wake line was serviced since [`suspend_irqs`].
+pub fn is_woken() -> bool { WOKEN.load(Ordering::Relaxed) }
+
+/// Unmask the lines masked by [`suspend_irqs`].
+pub fn resume_irqs() {
+    let _guard = InterruptGuard::new();
+    let cnts = UNMASK_CNTS.lock();
+    WAKE_LINES.store(0, Ordering::Relaxed);
+    for irq in 0..IRQ_LINE_CNT as u8 {
+        if cnts[irq as usize] != 0 {
+            pic::unmask(irq);
+        }
+    }
+}
+
+fn unmask_line(cnts: &mut [usize; IRQ_LINE_CNT], irq: u8) {
+    cnts[irq as usize] += 1;
+    if cnts[irq as usize] == 1 {
+        if irq >= 8 {
+            unmask_line(cnts, CASCADE_IRQ);
+        }
+        pic::unmask(irq);
+    }
+}
+
+fn mask_line(cnts: &mut [usize; IRQ_LINE_CNT], irq: u8) {
+    cnts[irq as usize] -= 1;
+    if cnts[irq as usize] == 0 {
+        pic::mask(irq);
+        if irq >= 8 {
+            mask_line(cnts, CASCADE_IRQ);
+        }
+    }
+}
+
+/// Returns the claim statistics of `irq`, or `None` if `irq` is not a valid
+/// line.
+pub fn irq_line_stats(irq: u8) -> Option<IrqLineStats> {
+    let line = IRQ_LINES.get(irq as usize)?;
+
+    let _guard = InterruptGuard::new();
+    let claim_cnts = line
+        .actions
+        .lock()
+        .iter()
+        .map(|action| (action.handler, action.claim_cnt))
+        .collect();
+    let spurious_cnt = line.spurious_cnt.load(Ordering::Relaxed);
+    Some(IrqLineStats {
+        claim_cnts,
+        spurious_cnt,
+    })
+}
+
+/// Call the handlers of `irq` until one of them claims the interrupt. If no
+/// handler claims it, the interrupt is recorded as spurious.
+pub(super) fn dispatch(irq: u8) -> IrqReturn {
+    IRQ_STAT.inc();
+    let Some(line) = IRQ_LINES.get(irq as usize) else {
+        SPURIOUS_IRQ_STAT.inc();
+        return IrqReturn::NotMine;
+    };
+
+    let mut actions = line.actions.lock();
+    for action in actions.iter_mut() {
+        if (action.handler)() == IrqReturn::Handled {
+            action.claim_cnt += 1;
+            random::add_irq_randomness(irq);
+            if WAKE_LINES.load(Ordering::Relaxed) & (1 << irq) != 0 {
+                WOKEN.store(true, Ordering::Relaxed);
+            }
+            return IrqReturn::Handled;
+        }
+    }
+
+    let spurious_cnt = line.spurious_cnt.fetch_add(1, Ordering::Relaxed) + 1;
+    if spurious_cnt % IRQ_STORM_SPURIOUS == 0 {
+        flight::record(EventKind::IrqStorm, irq as u64);
+    }
+    SPURIOUS_IRQ_STAT.inc();
+    IrqReturn::NotMine
+}
@@ -0,0 +1,91 @@
+//! Console output teed to every registered sink.
+//!
+//! Each sink keeps its own cursor and line state, so the same stream is
+//! rendered correctly on each of them. The sinks are picked on the command
+//! line with `console=`, a comma separated list of sink names, defaulting to
+//! every built-in sink.
+
+use core::fmt::{self, Write};
+
+use arrayvec::ArrayVec;
+
+use crate::boot::cmdline;
+use crate::drivers::serial::SERIAL;
+use crate::drivers::vga::VGA_BUFFER;
+use crate::interrupt::InterruptGuard;
+use crate::klog;
+
+const SINKS_LEN: usize = 4;
+
+static SINKS: spin::Mutex<ArrayVec<&'static dyn ConsoleSink, SINKS_LEN>> =
+    spin::Mutex::new(ArrayVec::new_const());
+
+/// Sinks available to `console=`.
+const BUILTIN_SINKS: &[&'static dyn ConsoleSink] = &[&VgaSink, &SerialSink];
+
+/// An output device rendering the console stream.
+pub trait ConsoleSink: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Render `s`, interpreting `\n` and backspace against the sink's own
+    /// cursor.
+    fn write_str(&self, s: &str);
+}
+
+/// Writes to every registered sink.
+///
+/// Each sink is locked only for the duration of each write, so that a
+/// long-lived writer does not starve other users of the sinks.
+pub struct Console;
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _guard = InterruptGuard::new();
+        for sink in SINKS.lock().iter() {
+            sink.write_str(s);
+        }
+        Ok(())
+    }
+}
+
+/// Register the built-in sinks picked by `console=`.
+pub fn init() {
+    let names = cmdline::get("console");
+    for sink in BUILTIN_SINKS {
+        if names.is_none_or(|names| names.split(',').any(|name| name == sink.name())) {
+            register(*sink);
+        }
+    }
+    if let Some(names) = names {
+        for name in names.split(',') {
+            if !BUILTIN_SINKS.iter().any(|sink| sink.name() == name) {
+                klog!(Warn, "console: unknown sink {}", name);
+            }
+        }
+    }
+}
+
+/// Register `sink` to receive the console stream.
+///
+/// # Panics
+/// Panics if the registry is full.
+pub fn register(sink: &'static dyn ConsoleSink) {
+    let _guard = InterruptGuard::new();
+    SINKS
+        .lock()
+        .try_push(sink)
+        .expect("console sink registry should not be full");
+}
+
+struct VgaSink;
+impl ConsoleSink for VgaSink {
+    fn name(&self) -> &'static str { "vga" }
+
+    fn write_str(&self, s: &str) { VGA_BUFFER.lock().write_str(s).ok(); }
+}
+
+struct SerialSink;
+impl ConsoleSink for SerialSink {
+    fn name(&self) -> &'static str { "serial" }
+
+    fn write_str(&self, s: &str) { SERIAL.lock().write_str(s).ok(); }
+}
@@ -28,8 +28,10 @@ impl VirtKeyboard {
             .view_bits_mut::<Lsb0>()
             .set(packet.0 as usize, packet.1);
         match packet {
-            (KEY_LEFTALT, is_press) => self.modifier.set(Modifier::ALT, is_press),
-            (KEY_LEFTCTRL, is_press) => self.modifier.set(Modifier::CTRL, is_press),
+            (KEY_LEFTALT, is_press) | (KEY_RIGHTALT, is_press) =>
+                self.modifier.set(Modifier::ALT, is_press),
+            (KEY_LEFTCTRL, is_press) | (KEY_RIGHTCTRL, is_press) =>
+                self.modifier.set(Modifier::CTRL, is_press),
             (KEY_LEFTSHIFT, is_press) | (KEY_RIGHTSHIFT, is_press) =>
                 self.modifier.set(Modifier::SHIFT, is_press),
 
@@ -160,5 +162,20 @@ pub mod keycode {
     pub const KEY_F11: KeyCode = 87;
     pub const KEY_F12: KeyCode = 88;
 
-    pub const KEYCODE_MAX: KeyCode = 88;
+    pub const KEY_KPENTER: KeyCode = 96;
+    pub const KEY_RIGHTCTRL: KeyCode = 97;
+    pub const KEY_KPSLASH: KeyCode = 98;
+    pub const KEY_RIGHTALT: KeyCode = 100;
+    pub const KEY_HOME: KeyCode = 102;
+    pub const KEY_UP: KeyCode = 103;
+    pub const KEY_PAGEUP: KeyCode = 104;
+    pub const KEY_LEFT: KeyCode = 105;
+    pub const KEY_RIGHT: KeyCode = 106;
+    pub const KEY_END: KeyCode = 107;
+    pub const KEY_DOWN: KeyCode = 108;
+    pub const KEY_PAGEDOWN: KeyCode = 109;
+    pub const KEY_INSERT: KeyCode = 110;
+    pub const KEY_DELETE: KeyCode = 111;
+
+    pub const KEYCODE_MAX: KeyCode = 111;
 }
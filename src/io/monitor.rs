@@ -1,26 +1,825 @@
-use core::fmt::Write as _;
+use core::fmt::{self, Write};
+use core::ptr;
+use core::str::SplitWhitespace;
+use core::sync::atomic::{AtomicBool, Ordering};
 
+use arraydeque::{ArrayDeque, Wrapping};
+use arrayvec::ArrayString;
+
+use super::console::Console;
 use super::keyboard::keycode::*;
 use super::keyboard::{KeyEvent, Keyboard, Modifier};
-use crate::common::hlt;
-use crate::drivers::vga::VGA_BUFFER;
+#[cfg(feature = "lockstat")]
+use crate::common::lock;
+use crate::common::stack;
+use crate::debug::{self, BreakKind, BreakLen, Breakpoint};
+use crate::drivers::ps2;
+#[cfg(feature = "ktest")]
+use crate::fault;
+use crate::mem::addr::PageSize;
+use crate::mem::Flag;
+use crate::{boot, cpu, flight, interrupt, mem, power, stats, sys, time};
+
+const PROMPT: &str = "> ";
+const LINE_LEN: usize = 76;
+/// Number of lines kept in the history.
+const HISTORY_LEN: usize = 16;
+/// Longest dump of the `md` command, which fits on one screen.
+const MD_LEN_MAX: usize = 256;
+const MD_BYTES_PER_LINE: usize = 16;
+
+/// Whether `mw` may write to memory.
+static UNSAFE: AtomicBool = AtomicBool::new(false);
 
 pub struct Monitor<'kb> {
     keyboard: &'kb mut dyn Keyboard,
+    line: ArrayString<LINE_LEN>,
+    history: ArrayDeque<ArrayString<LINE_LEN>, HISTORY_LEN, Wrapping>,
+    /// Age of the recalled history line, where 0 is the newest.
+    history_pos: Option<usize>,
+    /// Query of the ongoing history search.
+    search: Option<ArrayString<LINE_LEN>>,
 }
 impl<'kb> Monitor<'kb> {
-    pub fn new(kb: &'kb mut dyn Keyboard) -> Self { Self { keyboard: kb } }
-    pub fn start(&mut self) {
-        let mut console = VGA_BUFFER.lock();
+    pub fn new(kb: &'kb mut dyn Keyboard) -> Self {
+        Self {
+            keyboard: kb,
+            line: ArrayString::new(),
+            history: ArrayDeque::new(),
+            history_pos: None,
+            search: None,
+        }
+    }
+
+    pub fn start(&mut self) -> ! {
+        let mut console = Console;
+        console.write_str(PROMPT).ok();
         loop {
-            let ke = self.keyboard.next();
-            let ascii = ke.and_then(ketoa);
-            let Some(ascii) = ascii else {
+            let ke = time::wait_until(|| self.keyboard.next());
+            if ke.is_press && self.edit(&mut console, ke) {
+                continue;
+            }
+            let Some(ascii) = ketoa(ke) else {
+                continue;
+            };
+            self.search = None;
+
+            match ascii {
+                b'\n' => {
+                    console.write_char('\n').ok();
+                    execute(&mut console, &self.line).ok();
+                    self.push_history();
+                    self.line.clear();
+                    console.write_str(PROMPT).ok();
+                },
+                0x8 =>
+                    if self.line.pop().is_some() {
+                        console.write_char(ascii as char).ok();
+                    },
+                _ =>
+                    if self.line.try_push(ascii as char).is_ok() {
+                        console.write_char(ascii as char).ok();
+                    },
+            }
+        }
+    }
+
+    /// Handle a line editing key. Returns whether `ke` is one.
+    fn edit(&mut self, console: &mut Console, ke: KeyEvent) -> bool {
+        match ke.key {
+            KEY_UP => self.recall(console, true),
+            KEY_DOWN => self.recall(console, false),
+            KEY_TAB => self.complete(console),
+            KEY_R if ke.modifier.contains(Modifier::CTRL) => self.search_history(console),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Replace the edited line with `line` on the console.
+    fn set_line(&mut self, console: &mut Console, line: &str) {
+        for _ in 0..self.line.len() {
+            console.write_char('\x08').ok();
+        }
+        self.line.clear();
+        self.line.push_str(line);
+        console.write_str(line).ok();
+    }
+
+    fn push_history(&mut self) {
+        self.history_pos = None;
+        let is_repeat = self.history.back() == Some(&self.line);
+        if !self.line.trim().is_empty() && !is_repeat {
+            self.history.push_back(self.line);
+        }
+    }
+
+    /// Recall the next older line if `is_older`, or else the next newer line.
+    /// Going past the newest line clears the line.
+    fn recall(&mut self, console: &mut Console, is_older: bool) {
+        self.search = None;
+        let pos = match (self.history_pos, is_older) {
+            (None, true) => 0,
+            (Some(pos), true) => pos + 1,
+            (None, false) => return,
+            (Some(0), false) => {
+                self.history_pos = None;
+                self.set_line(console, "");
+                return;
+            },
+            (Some(pos), false) => pos - 1,
+        };
+        let Some(line) = self.history_line(pos) else {
+            return;
+        };
+        self.history_pos = Some(pos);
+        self.set_line(console, &line);
+    }
+
+    /// Recall the next older line containing the search query. The query is
+    /// the edited line when a search starts, and is kept until another key is
+    /// typed.
+    fn search_history(&mut self, console: &mut Console) {
+        let query = *self.search.get_or_insert(self.line);
+        let start = self.history_pos.map_or(0, |pos| pos + 1);
+        let found = (start..self.history.len()).find(|pos| {
+            self.history_line(*pos)
+                .is_some_and(|line| line.contains(query.as_str()))
+        });
+        let Some(pos) = found else {
+            return;
+        };
+        let line = self
+            .history_line(pos)
+            .expect("found line should be in the history");
+        self.history_pos = Some(pos);
+        self.set_line(console, &line);
+    }
+
+    fn history_line(&self, pos: usize) -> Option<ArrayString<LINE_LEN>> {
+        let idx = self.history.len().checked_sub(pos + 1)?;
+        self.history.get(idx).copied()
+    }
+
+    /// Complete the command name being typed. If the name is ambiguous, it is
+    /// extended to the longest common prefix, or the candidates are listed
+    /// if it cannot be extended.
+    fn complete(&mut self, console: &mut Console) {
+        self.search = None;
+        if self.line.contains(' ') {
+            return;
+        }
+        let mut candidates = COMMANDS
+            .iter()
+            .map(|cmd| cmd.name)
+            .filter(|name| name.starts_with(self.line.as_str()));
+        let Some(first) = candidates.next() else {
+            return;
+        };
+        let mut prefix = first;
+        let mut candidate_cnt = 1;
+        for name in candidates {
+            let common_len = Iterator::zip(prefix.bytes(), name.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            prefix = &prefix[..common_len];
+            candidate_cnt += 1;
+        }
+
+        if candidate_cnt == 1 {
+            let mut line = ArrayString::<LINE_LEN>::new();
+            line.push_str(first);
+            line.try_push(' ').ok();
+            self.set_line(console, &line);
+        } else if prefix.len() > self.line.len() {
+            self.set_line(console, prefix);
+        } else {
+            console.write_char('\n').ok();
+            for name in COMMANDS.iter().map(|cmd| cmd.name) {
+                if name.starts_with(self.line.as_str()) {
+                    write!(console, "{} ", name).ok();
+                }
+            }
+            write!(console, "\n{}{}", PROMPT, self.line).ok();
+        }
+    }
+}
+
+type Args<'a> = SplitWhitespace<'a>;
+
+/// A monitor command.
+struct Command {
+    name: &'static str,
+    help: &'static str,
+    run: fn(&mut dyn Write, Args) -> fmt::Result,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "list commands",
+        run: help,
+    },
+    Command {
+        name: "uname",
+        help: "show the kernel version and build",
+        run: show_uname,
+    },
+    Command {
+        name: "cpus",
+        help: "list CPUs from the firmware",
+        run: show_cpus,
+    },
+    Command {
+        name: "stats",
+        help: "show kernel statistics",
+        run: show_stats,
+    },
+    Command {
+        name: "irqs",
+        help: "show IRQ lines and handler claims",
+        run: show_irqs,
+    },
+    Command {
+        name: "top",
+        help: "top [SECS], show CPU load every second for up to 60s",
+        run: top,
+    },
+    Command {
+        name: "stacks",
+        help: "show the deepest usage of kernel stacks",
+        run: show_stacks,
+    },
+    Command {
+        name: "locks",
+        help: "show lock contention",
+        run: show_locks,
+    },
+    Command {
+        name: "md",
+        help: "md ADDR [LEN], dump kernel memory",
+        run: dump_memory,
+    },
+    Command {
+        name: "mw",
+        help: "mw ADDR VAL [LEN], write kernel memory",
+        run: write_memory,
+    },
+    Command {
+        name: "unsafe",
+        help: "unsafe on|off, allow mw",
+        run: set_unsafe,
+    },
+    Command {
+        name: "vtop",
+        help: "vtop ADDR, walk the kernel paging tables",
+        run: walk_page_tables,
+    },
+    Command {
+        name: "fail",
+        help: "fail [SITE N], list fault sites or fail every Nth call",
+        run: inject_fault,
+    },
+    Command {
+        name: "bp",
+        help: "bp [x|w|rw ADDR [LEN]], list or set breakpoints",
+        run: set_breakpoint,
+    },
+    Command {
+        name: "bpclear",
+        help: "bpclear SLOT, clear a breakpoint",
+        run: clear_breakpoint,
+    },
+    Command {
+        name: "ps2reset",
+        help: "reset and re-initialize the PS/2 controller",
+        run: reset_ps2,
+    },
+    Command {
+        name: "suspend",
+        help: "suspend until a key is pressed",
+        run: suspend,
+    },
+    Command {
+        name: "events",
+        help: "show the flight recorder events",
+        run: events,
+    },
+    Command {
+        name: "reboot",
+        help: "reboot the machine",
+        run: reboot,
+    },
+    Command {
+        name: "shutdown",
+        help: "shut down drivers and power off",
+        run: shutdown,
+    },
+];
+
+fn execute(console: &mut dyn Write, line: &str) -> fmt::Result {
+    let mut args = line.split_whitespace();
+    let Some(name) = args.next() else {
+        return Ok(());
+    };
+
+    match COMMANDS.iter().find(|cmd| cmd.name == name) {
+        Some(cmd) => (cmd.run)(console, args),
+        None => writeln!(console, "unknown command: {}", name),
+    }
+}
+
+fn help(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    for cmd in COMMANDS {
+        writeln!(console, "{:<10}{}", cmd.name, cmd.help)?;
+    }
+    Ok(())
+}
+
+fn show_uname(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    writeln!(console, "{}", sys::uname())
+}
+
+fn show_cpus(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    for cpu in cpu::topology::cpus() {
+        writeln!(
+            console,
+            "cpu{:<3} apic {:<3} acpi {:<3} {}",
+            cpu.id,
+            cpu.apic_id,
+            cpu.acpi_id,
+            if cpu.is_enabled {
+                "enabled"
+            } else {
+                "online capable"
+            }
+        )?;
+    }
+    Ok(())
+}
+
+fn show_stats(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    let mut res = Ok(());
+    stats::for_each(|stat| {
+        if res.is_ok() {
+            res = writeln!(
+                console,
+                "{:<32}{}",
+                stat.name(),
+                stat.get()
+            );
+        }
+    });
+    res
+}
+
+fn show_irqs(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    for irq in 0..interrupt::IRQ_LINE_CNT as u8 {
+        let Some(stats) = interrupt::irq_line_stats(irq) else {
+            continue;
+        };
+        if stats.claim_cnts.is_empty() && stats.spurious_cnt == 0 {
+            continue;
+        }
+        let is_masked = interrupt::is_irq_masked(irq).unwrap_or(true);
+        write!(
+            console,
+            "irq{:<3}{:<9}spurious {:<6}",
+            irq,
+            if is_masked {
+                "masked"
+            } else {
+                "unmasked"
+            },
+            stats.spurious_cnt
+        )?;
+        for (handler, cnt) in &stats.claim_cnts {
+            write!(
+                console,
+                " {:#x}:{}",
+                *handler as usize, cnt
+            )?;
+        }
+        writeln!(console)?;
+    }
+    Ok(())
+}
+
+fn top(console: &mut dyn Write, mut args: Args) -> fmt::Result {
+    const DEFAULT_SECS: u64 = 5;
+    /// The monitor cannot be interrupted, so `top` should not hold it long.
+    const MAX_SECS: u64 = 60;
+
+    let secs = args
+        .next()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_SECS)
+        .clamp(1, MAX_SECS);
+    for _ in 0..secs {
+        let deadline = time::jiffies() + time::ms_to_jiffies(time::LOAD_WINDOW_MS);
+        time::wait_until(|| (time::jiffies() >= deadline).then_some(()));
+
+        writeln!(
+            console,
+            "uptime {} ms",
+            time::uptime_ms()
+        )?;
+        for cpu in cpu::topology::cpus().iter().map(|cpu| cpu.id) {
+            let Some(total) = time::cpu_time(cpu) else {
                 continue;
             };
-            console.write_u8(ascii);
+            if total.idle + total.busy == 0 {
+                continue;
+            }
+            let load = time::cpu_load(cpu).unwrap_or_default();
+            writeln!(
+                console,
+                "cpu{:<3} busy {:>3}%  total busy {:>3}%",
+                cpu,
+                load.busy_percent(),
+                total.busy_percent()
+            )?;
         }
     }
+    Ok(())
+}
+
+fn show_stacks(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    let stacks = [
+        ("boot", boot::boot_stack()),
+        ("double fault", interrupt::df_stack()),
+    ];
+    writeln!(
+        console,
+        "{:<16}{:>10}{:>10}",
+        "stack", "max used", "size"
+    )?;
+    for (name, range) in stacks {
+        // SAFETY: Both stacks are painted during initialization.
+        let depth = unsafe { stack::max_depth(range.clone()) };
+        writeln!(
+            console,
+            "{:<16}{:>10}{:>10}",
+            name,
+            depth,
+            range.len()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "lockstat")]
+fn show_locks(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    let mut res = writeln!(
+        console,
+        "{:<16}{:>10}{:>10}{:>14}{:>12}",
+        "name", "acquired", "contended", "wait", "max wait"
+    );
+    lock::for_each(|lock| {
+        if res.is_err() {
+            return;
+        }
+        res = writeln!(
+            console,
+            "{:<16}{:>10}{:>10}{:>14}{:>12}",
+            lock.name(),
+            lock.acquire_cnt(),
+            lock.contend_cnt(),
+            lock.wait_cycles(),
+            lock.max_wait_cycles()
+        );
+        if let (Ok(()), Some(holder)) = (res, lock.contended_holder()) {
+            res = writeln!(
+                console,
+                "  held at {}:{}",
+                holder.file(),
+                holder.line()
+            );
+        }
+    });
+    res
+}
+
+#[cfg(not(feature = "lockstat"))]
+fn show_locks(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    writeln!(
+        console,
+        "lock contention is recorded with the lockstat feature"
+    )
+}
+
+fn dump_memory(console: &mut dyn Write, mut args: Args) -> fmt::Result {
+    let Some(addr) = args.next().and_then(parse_hex) else {
+        return writeln!(console, "expected hex address");
+    };
+    let len = match args.next() {
+        Some(len) => len.parse().ok(),
+        None => Some(MD_BYTES_PER_LINE),
+    };
+    let Some(len) = len.filter(|len| (1..=MD_LEN_MAX).contains(len)) else {
+        return writeln!(
+            console,
+            "length must be 1 to {}",
+            MD_LEN_MAX
+        );
+    };
+    if !is_kernel_mapped(addr, len) {
+        return writeln!(
+            console,
+            "{:#x} is not mapped kernel memory",
+            addr
+        );
+    }
+
+    for line in (addr..addr + len).step_by(MD_BYTES_PER_LINE) {
+        write!(console, "{:016x}:", line)?;
+        for byte in line..(line + MD_BYTES_PER_LINE).min(addr + len) {
+            // SAFETY: The range is checked to be mapped.
+            let byte = unsafe { ptr::read_volatile(byte as *const u8) };
+            write!(console, " {:02x}", byte)?;
+        }
+        writeln!(console)?;
+    }
+    Ok(())
+}
+
+fn write_memory(console: &mut dyn Write, mut args: Args) -> fmt::Result {
+    if !UNSAFE.load(Ordering::Relaxed) {
+        return writeln!(
+            console,
+            "mw is disabled, enable it with `unsafe on`"
+        );
+    }
+    let (Some(addr), Some(val)) = (
+        args.next().and_then(parse_hex),
+        args.next().and_then(parse_hex),
+    ) else {
+        return writeln!(
+            console,
+            "expected hex address and value"
+        );
+    };
+    let len = match args.next() {
+        Some(len) => len.parse().ok(),
+        None => Some(8),
+    };
+    let Some(len) = len.filter(|len| matches!(len, 1 | 2 | 4 | 8)) else {
+        return writeln!(console, "length must be 1, 2, 4 or 8");
+    };
+    if addr % len != 0 {
+        return writeln!(
+            console,
+            "address must be aligned to length"
+        );
+    }
+    if !is_kernel_mapped(addr, len) {
+        return writeln!(
+            console,
+            "{:#x} is not mapped kernel memory",
+            addr
+        );
+    }
+
+    // SAFETY: The address is mapped, aligned and the user asked for it.
+    unsafe {
+        match len {
+            1 => ptr::write_volatile(addr as *mut u8, val as u8),
+            2 => ptr::write_volatile(addr as *mut u16, val as u16),
+            4 => ptr::write_volatile(addr as *mut u32, val as u32),
+            _ => ptr::write_volatile(addr as *mut u64, val as u64),
+        }
+    }
+    Ok(())
+}
+
+fn set_unsafe(console: &mut dyn Write, mut args: Args) -> fmt::Result {
+    match args.next() {
+        Some("on") => {
+            UNSAFE.store(true, Ordering::Relaxed);
+            writeln!(
+                console,
+                "mw enabled, writes are not checked beyond mapping"
+            )
+        },
+        Some("off") => {
+            UNSAFE.store(false, Ordering::Relaxed);
+            Ok(())
+        },
+        _ => writeln!(
+            console,
+            "unsafe is {}",
+            if UNSAFE.load(Ordering::Relaxed) {
+                "on"
+            } else {
+                "off"
+            }
+        ),
+    }
+}
+
+fn walk_page_tables(console: &mut dyn Write, mut args: Args) -> fmt::Result {
+    const FLAG_NAMES: [(Flag, &str); 5] = [
+        (Flag::Present, "P"),
+        (Flag::ReadWrite, "RW"),
+        (Flag::UserSuper, "US"),
+        (Flag::PageSize, "PS"),
+        (Flag::Global, "G"),
+    ];
+
+    let Some(addr) = args.next().and_then(parse_hex) else {
+        return writeln!(console, "expected hex address");
+    };
+    if args.next().is_some() {
+        return writeln!(
+            console,
+            "only the kernel memory map can be walked"
+        );
+    }
+
+    let mut res = Ok(());
+    let mut paddr = None;
+    let is_kernel = mem::walk_kernel(addr, |entry| {
+        if res.is_err() {
+            return;
+        }
+        res = write!(
+            console,
+            "{:<5}{:016x}",
+            entry.level_name(),
+            entry.raw()
+        );
+        for (flag, name) in FLAG_NAMES {
+            if res.is_ok() && entry.flag(flag) == Some(true) {
+                res = write!(console, " {}", name);
+            }
+        }
+        if res.is_ok() {
+            res = writeln!(console);
+        }
+        paddr = entry
+            .page()
+            .map(|(size, base)| base.usize() + (addr & (size.usize() - 1)));
+    });
+    res?;
+
+    match (is_kernel, paddr) {
+        (false, _) => writeln!(
+            console,
+            "{:#x} is not a kernel address",
+            addr
+        ),
+        (true, Some(paddr)) => writeln!(console, "{:#x} -> {:#x}", addr, paddr),
+        (true, None) => writeln!(console, "{:#x} is not mapped", addr),
+    }
+}
+
+/// Returns whether `len` bytes from `addr` are mapped in the kernel address
+/// spaces.
+fn is_kernel_mapped(addr: usize, len: usize) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    let page = PageSize::MIN.usize();
+    (addr & !(page - 1)..end)
+        .step_by(page)
+        .all(|page| mem::translate_kernel(page).is_some())
+}
+
+#[cfg(feature = "ktest")]
+fn inject_fault(console: &mut dyn Write, mut args: Args) -> fmt::Result {
+    let Some(name) = args.next() else {
+        let mut res = Ok(());
+        fault::for_each(|site| {
+            if res.is_ok() {
+                res = writeln!(
+                    console,
+                    "{:<20} every {:>6} failed {}",
+                    site.name(),
+                    site.interval(),
+                    site.fail_cnt()
+                );
+            }
+        });
+        return res;
+    };
+    let Some(site) = fault::find(name) else {
+        return writeln!(console, "no such fault site: {}", name);
+    };
+    let Some(interval) = args.next().and_then(|n| n.parse().ok()) else {
+        return writeln!(console, "expected interval");
+    };
+    site.set_interval(interval);
+    Ok(())
+}
+
+#[cfg(not(feature = "ktest"))]
+fn inject_fault(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    writeln!(
+        console,
+        "fault injection is enabled with the ktest feature"
+    )
+}
+
+fn set_breakpoint(console: &mut dyn Write, mut args: Args) -> fmt::Result {
+    let Some(kind) = args.next() else {
+        for slot in 0..debug::BREAKPOINT_CNT {
+            if let Some((bp, hits)) = debug::breakpoint(slot) {
+                writeln!(
+                    console,
+                    "{}: {:?} {:#x} len {} hits {} last ip {:#x}",
+                    slot,
+                    bp.kind,
+                    bp.addr,
+                    bp.len.bytes(),
+                    hits.cnt,
+                    hits.last_ip
+                )?;
+            }
+        }
+        return Ok(());
+    };
+
+    let kind = match kind {
+        "x" => BreakKind::Execute,
+        "w" => BreakKind::Write,
+        "rw" => BreakKind::ReadWrite,
+        _ =>
+            return writeln!(
+                console,
+                "unknown breakpoint kind: {}",
+                kind
+            ),
+    };
+    let Some(addr) = args.next().and_then(parse_hex) else {
+        return writeln!(console, "expected hex address");
+    };
+    let len = match args.next() {
+        Some(len) => len.parse().ok().and_then(BreakLen::from_bytes),
+        None => Some(BreakLen::B1),
+    };
+    let Some(len) = len else {
+        return writeln!(console, "length must be 1, 2, 4 or 8");
+    };
+
+    let breakpoint = Breakpoint {
+        addr,
+        kind,
+        len,
+        handler: None,
+    };
+    match debug::set_breakpoint(breakpoint) {
+        Ok(slot) => writeln!(
+            console,
+            "breakpoint set in slot {}",
+            slot
+        ),
+        Err(err) => writeln!(
+            console,
+            "failed to set breakpoint: {}",
+            err
+        ),
+    }
+}
+
+fn clear_breakpoint(console: &mut dyn Write, mut args: Args) -> fmt::Result {
+    let slot = args.next().and_then(|slot| slot.parse().ok());
+    match slot.and_then(debug::clear_breakpoint) {
+        Some(_) => Ok(()),
+        None => writeln!(console, "no such breakpoint"),
+    }
+}
+
+fn reset_ps2(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    match ps2::reinit() {
+        Ok(()) => Ok(()),
+        Err(err) => writeln!(console, "ps2 reset failed: {:?}", err),
+    }
+}
+
+fn suspend(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    match power::suspend() {
+        Ok(()) => writeln!(console, "resumed"),
+        Err(err) => writeln!(console, "suspend failed: {}", err),
+    }
+}
+
+fn events(console: &mut dyn Write, _args: Args) -> fmt::Result {
+    let mut res = Ok(());
+    flight::for_each(|event| {
+        if res.is_ok() {
+            res = writeln!(console, "{}", event);
+        }
+    });
+    res
+}
+
+fn reboot(_console: &mut dyn Write, _args: Args) -> fmt::Result { power::reboot() }
+
+fn shutdown(_console: &mut dyn Write, _args: Args) -> fmt::Result { power::shutdown() }
+
+fn parse_hex(s: &str) -> Option<usize> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    usize::from_str_radix(s, 16).ok()
 }
 
 fn ketoa(ke: KeyEvent) -> Option<u8> {
@@ -28,7 +827,8 @@ fn ketoa(ke: KeyEvent) -> Option<u8> {
         return None;
     }
 
-    let is_cap = ke.modifier.contains(Modifier::CAPSLOCK) ^ ke.modifier.contains(Modifier::SHIFT);
+    let is_shift = ke.modifier.contains(Modifier::SHIFT);
+    let is_cap = ke.modifier.contains(Modifier::CAPSLOCK) ^ is_shift;
     let cap_offset = 32 * is_cap as u8;
     match ke.key {
         KEY_0 => Some(b'0'),
@@ -61,6 +861,11 @@ fn ketoa(ke: KeyEvent) -> Option<u8> {
         KEY_Y => Some(b'y' - cap_offset),
         KEY_Z => Some(b'z' - cap_offset),
 
+        // Fault sites and stats are named like `mem.alloc_pages`.
+        KEY_MINUS if is_shift => Some(b'_'),
+        KEY_MINUS => Some(b'-'),
+        KEY_DOT => Some(b'.'),
+
         KEY_ENTER => Some(b'\n'),
         KEY_SPACE => Some(b' '),
         KEY_BACKSPACE => Some(0x8),
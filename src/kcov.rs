@@ -0,0 +1,82 @@
+//! Coverage of kernel code run by the in-kernel tests.
+//!
+//! With `make KCOV=1`, every edge is instrumented with the sanitizer coverage
+//! guard callback. Each guard records the address of its edge the first time
+//! it is hit, and [`dump`] writes the recorded addresses to the serial port
+//! to be symbolized on the host.
+//!
+//! The callbacks are written in assembly so that they are not instrumented
+//! themselves.
+
+use core::arch::global_asm;
+use core::fmt::Write as _;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::drivers::serial::SERIAL;
+
+const PCS_LEN: usize = 1 << 16;
+
+static mut PCS: [usize; PCS_LEN] = [0; PCS_LEN];
+static PC_CNT: AtomicUsize = AtomicUsize::new(0);
+
+global_asm!(
+    ".global __sanitizer_cov_trace_pc_guard",
+    "__sanitizer_cov_trace_pc_guard:",
+    "mov eax, dword ptr [rdi]",
+    "test eax, eax",
+    "jz 2f",
+    "mov dword ptr [rdi], 0",
+    "mov ecx, 1",
+    "lock xadd qword ptr [rip + {cnt}], rcx",
+    "cmp rcx, {len}",
+    "jae 2f",
+    "mov rax, qword ptr [rsp]",
+    "lea rdx, [rip + {pcs}]",
+    "mov qword ptr [rdx + rcx * 8], rax",
+    "2:",
+    "ret",
+    // Guards are armed by `init` instead, as constructors are not run.
+    ".global __sanitizer_cov_trace_pc_guard_init",
+    "__sanitizer_cov_trace_pc_guard_init:",
+    "ret",
+    len = const PCS_LEN,
+    cnt = sym PC_CNT,
+    pcs = sym PCS,
+);
+
+/// Arm every coverage guard.
+///
+/// This should be called as early as possible, since edges run before are
+/// not recorded.
+pub fn init() {
+    unsafe extern "C" {
+        static mut __start___sancov_guards: u32;
+        static mut __stop___sancov_guards: u32;
+    }
+    let start = &raw mut __start___sancov_guards;
+    let stop = &raw mut __stop___sancov_guards;
+    let mut guard = start;
+    while guard < stop {
+        // SAFETY: The guards section is writable and lies between the two
+        // linker symbols.
+        unsafe {
+            ptr::write_volatile(guard, 1);
+            guard = guard.add(1);
+        }
+    }
+}
+
+/// Write the address of every edge hit so far to the serial port, one per
+/// line between `kcov: begin` and `kcov: end` markers.
+pub fn dump() {
+    let cnt = PC_CNT.load(Ordering::Relaxed).min(PCS_LEN);
+    let mut serial = SERIAL.lock();
+    writeln!(serial, "kcov: begin {} edges", cnt).ok();
+    for idx in 0..cnt {
+        // SAFETY: Entries below the count are written once by the callback.
+        let pc = unsafe { ptr::read_volatile((&raw const PCS).cast::<usize>().add(idx)) };
+        writeln!(serial, "{:#x}", pc).ok();
+    }
+    writeln!(serial, "kcov: end").ok();
+}
@@ -0,0 +1,153 @@
+//! Leveled kernel log routed to the console sinks.
+//!
+//! Each sink has its own minimum severity and format. The VGA console colors
+//! records by severity, COM1 gets plain lines, and COM2 optionally gets one
+//! JSON object per line for test harnesses to parse. The thresholds are set
+//! on the command line with `log.vga=`, `log.serial=` and `log.json=`, each
+//! taking a level name or `off`. The JSON sink is off unless given.
+//!
+//! ```text
+//! {"jiffies":42,"level":"warn","msg":"ps2: keyboard unavailable: NoDev"}
+//! ```
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::boot::cmdline;
+use crate::drivers::serial::{AUX_SERIAL, SERIAL};
+use crate::drivers::vga::{Color, VGA_BUFFER};
+use crate::time;
+
+/// Threshold disabling a sink.
+const OFF: u8 = 0;
+
+static VGA_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static SERIAL_LEVEL: AtomicU8 = AtomicU8::new(Level::Debug as u8);
+static JSON_LEVEL: AtomicU8 = AtomicU8::new(OFF);
+
+/// Log a formatted line at `$level`, a [`Level`] variant name.
+///
+/// The line should not end with a newline.
+#[macro_export]
+macro_rules! klog {
+    ($level:ident, $($arg:tt)*) => {
+        $crate::klog::log($crate::klog::Level::$level, format_args!($($arg)*))
+    };
+}
+
+/// Severity of a record, most severe first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+impl Level {
+    const ALL: [Level; 4] = [Level::Error, Level::Warn, Level::Info, Level::Debug];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Level::Error => Color::Red,
+            Level::Warn => Color::Brown,
+            Level::Info => Color::Gray,
+            Level::Debug => Color::Cyan,
+        }
+    }
+}
+
+/// Read the sink thresholds from the command line.
+///
+/// Records logged before use the default thresholds.
+pub fn init() {
+    for (key, threshold) in [
+        ("log.vga", &VGA_LEVEL),
+        ("log.serial", &SERIAL_LEVEL),
+        ("log.json", &JSON_LEVEL),
+    ] {
+        let Some(value) = cmdline::get(key) else {
+            continue;
+        };
+        match parse_threshold(value) {
+            Some(level) => threshold.store(level, Ordering::Relaxed),
+            None => klog!(Warn, "klog: invalid {}={}", key, value),
+        }
+    }
+}
+
+/// Write a record at `level` to every sink accepting it.
+///
+/// This is normally called through [`klog!`].
+pub fn log(level: Level, args: fmt::Arguments) {
+    let is_enabled = |threshold: &AtomicU8| level as u8 <= threshold.load(Ordering::Relaxed);
+
+    if is_enabled(&VGA_LEVEL) {
+        let mut vga_buffer = VGA_BUFFER.lock();
+        let color_code = vga_buffer.color_code();
+        vga_buffer.set_color(
+            level.color(),
+            Color::Black,
+            level <= Level::Warn,
+        );
+        writeln!(vga_buffer, "{}", args).ok();
+        vga_buffer.set_color_code(color_code);
+    }
+    if is_enabled(&SERIAL_LEVEL) {
+        writeln!(
+            SERIAL.lock(),
+            "[{:>5}] {}",
+            level.name(),
+            args
+        )
+        .ok();
+    }
+    if is_enabled(&JSON_LEVEL) {
+        let mut serial = AUX_SERIAL.lock();
+        write!(
+            serial,
+            "{{\"jiffies\":{},\"level\":\"{}\",\"msg\":\"",
+            time::jiffies(),
+            level.name()
+        )
+        .ok();
+        write!(JsonEscape(&mut *serial), "{}", args).ok();
+        writeln!(serial, "\"}}").ok();
+    }
+}
+
+fn parse_threshold(value: &str) -> Option<u8> {
+    if value == "off" {
+        return Some(OFF);
+    }
+    Level::ALL
+        .into_iter()
+        .find(|level| level.name() == value)
+        .map(|level| level as u8)
+}
+
+/// Escapes text written through it as the inside of a JSON string.
+struct JsonEscape<W>(W);
+impl<W: Write> Write for JsonEscape<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                c if (c as u32) < 0x20 => write!(self.0, "\\u{:04x}", c as u32)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
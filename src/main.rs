@@ -16,45 +16,109 @@ extern crate alloc;
 
 use core::fmt::Write as _;
 
-use common::hlt;
+use common::stack;
 use drivers::ps2;
 use io::monitor::Monitor;
 use multiboot2::{BootInformation, BootInformationHeader};
 
+mod acpi;
 mod boot;
 mod common;
+mod cpu;
+mod debug;
 mod drivers;
+mod error;
+mod executor;
+mod fault;
+mod flight;
+mod gdt;
 mod interrupt;
 mod io;
+#[cfg(feature = "kcov")]
+mod kcov;
+mod klog;
 mod mem;
+mod phase;
+mod power;
+mod random;
+mod selfcheck;
+mod stats;
+mod sys;
+#[cfg(feature = "ktest")]
 mod test;
+mod time;
 mod usr;
 
 #[no_mangle]
 pub extern "C" fn kmain(mbi_ptr: u32) -> ! {
     use drivers::vga::*;
 
+    #[cfg(feature = "kcov")]
+    kcov::init();
+    // SAFETY: kmain runs on the boot stack.
+    unsafe { stack::paint_current(boot::boot_stack().start) };
+
     let mut vga_buffer = VGA_BUFFER.lock();
     vga_buffer.set_color(Color::Green, Color::Black, true);
     write!(*vga_buffer, "Hello from kernel!\n").expect("VGA text mode not available");
     vga_buffer.set_color(Color::Gray, Color::Black, true);
     drop(vga_buffer);
 
+    log!("{}\n", sys::uname());
+
     let boot_info = unsafe { BootInformation::load(mbi_ptr as *const BootInformationHeader) };
     let boot_info = boot_info.expect("boot info not found");
 
-    log!("boot info found\n");
+    klog!(Info, "boot info found");
+
+    boot::init(&boot_info);
+    klog::init();
+    flight::init(&boot_info);
+    cpu::features::init();
+    random::init();
 
+    gdt::init_boot();
     mem::init(boot_info);
-    test::test_mem();
-    log!("mem initalized\n");
+    #[cfg(feature = "ktest")]
+    test::run_mem_tests();
+    klog!(Info, "mem initalized");
+
+    cpu::topology::init();
+
+    gdt::init();
+    klog!(Info, "gdt initialized");
 
     interrupt::init();
-    log!("interrupt initialized\n");
+    #[cfg(feature = "ktest")]
+    test::test_user_access();
+    klog!(Info, "interrupt initialized");
+
+    selfcheck::run();
 
     drivers::init();
-    log!("drivers initialized\n");
+    io::console::init();
+    klog!(Info, "drivers initialized");
+
+    time::init();
+    klog!(
+        Info,
+        "time initialized at {} Hz",
+        time::tick_hz()
+    );
+    drivers::vga::start_refresh();
+    #[cfg(feature = "ktest")]
+    test::test_executor();
+
+    #[cfg(feature = "examples")]
+    test::test_examples();
+
+    #[cfg(feature = "kcov")]
+    kcov::dump();
 
     log!("\nkernel initialized\n");
-    hlt()
+
+    let keyboard = ps2::KEYBOARD.get().expect("keyboard should be initialized");
+    // SAFETY: The monitor is the only consumer of the keyboard.
+    let keyboard = unsafe { keyboard.get().as_mut_unchecked() };
+    Monitor::new(keyboard).start()
 }
@@ -1,126 +1,117 @@
-use core::arch::asm;
-use core::ops::Range;
-
 use addr::{Addr, AddrSpace, PageAddr};
-use bitvec::field::BitField;
-use bitvec::order::Lsb0;
-use bitvec::view::BitView;
 use multiboot2::BootInformation;
-use paging::{Flag, MemoryManager, MMU};
-use virt::{KernelImageSpace, PhysicalRemapSpace};
+use paging::{MemoryManager, MemoryMap as _, MMU};
+use virt::{DataStackSpace, KernelImageSpace, VirtSpace};
 
 
 pub mod addr;
 mod alloc;
+pub mod dma;
+pub mod layout;
 mod paging;
 mod phy;
+pub mod pressure;
+pub mod user;
 mod virt;
 
-pub use alloc::{GlobalAllocator, PageAllocator};
+pub use alloc::{GlobalAllocator, PageAllocator, SlabAllocator};
 
-pub use paging::{X86_64MemoryManager, X86_64MemoryMap};
+pub use paging::{Flag, WalkEntry, X86_64MemoryManager, X86_64MemoryMap};
 pub use phy::UMASpace;
+pub use virt::PhysicalRemapSpace;
 
-use crate::common::{hlt, Privilege};
+use crate::common::{hlt, lock};
+use crate::phase::{self, Phase};
+use crate::{boot, fault, stats};
 
 const KERNEL_OFFSET_VMA: usize = 0xFFFFFFFF80000000;
 
 
-extern "C" {
-    static _KERNEL_START_VMA: u8;
-    static _KERNEL_END_VMA: u8;
-    static _KERNEL_START_LMA: u8;
-}
-
 /// Initialize paging and global/page allocators.
 pub fn init(boot_info: BootInformation) {
+    phase::enter(Phase::Mem);
     let memory_info = boot_info
         .memory_map_tag()
         .expect("Currently does not support uefi memory map");
-    init_gdtr();
     let bmm = phy::init_boot_mem(memory_info.memory_areas());
+    for module in boot::modules() {
+        bmm.reserve_range(module.range());
+    }
+    #[cfg(feature = "graphics-console")]
+    if let Some(framebuffer) = boot::framebuffer() {
+        bmm.reserve_range(framebuffer.range());
+    }
+    dma::init(&bmm);
     MMU.call_once(|| X86_64MemoryManager::init(&bmm));
     phy::init(bmm);
-}
-
 
-pub const fn kernel_offset_vma() -> usize { KERNEL_OFFSET_VMA }
-pub fn kernel_start_vma() -> Addr<KernelImageSpace> {
-    // SAFETY: _KERNEL_START_VMA is on symbol table created by linker. The
-    // address of the symbol is the virtual memory address of kernel.
-    Addr::from_ref(unsafe { &_KERNEL_START_VMA })
-}
-pub fn kernel_end_vma() -> Addr<KernelImageSpace> {
-    // SAFETY: _KERNEL_END_VMA is on symbol table created by linker. The
-    // address of the symbol is the virtual memory address of kernel.
-    Addr::from_ref(unsafe { &_KERNEL_END_VMA })
-}
-pub fn kernel_start_lma() -> Addr<UMASpace> {
-    // SAFETY: _KERNEL_START_LMA is on symbol table created by linker. The
-    // address of the symbol is the load memory address of kernel, which
-    // should be loaded during real mode at the actual physical address
-    unsafe { Addr::new(&_KERNEL_START_LMA as *const u8 as usize) }
+    lock::register(&paging::KERNEL_MAP_LOCK);
+    stats::register(&alloc::ALLOC_STAT);
+    stats::register(&alloc::DEALLOC_STAT);
+    stats::register(&alloc::SLAB_CAP_STAT);
+    alloc::init_slab_caps();
+    stats::register(&phy::ALLOCATED_FRAMES_STAT);
+    stats::register(&phy::FREE_FRAMES_STAT);
+    stats::register(&pressure::RECLAIMED_FRAMES_STAT);
+    pressure::register(&alloc::SLAB_SHRINKER);
+    fault::register(&alloc::ALLOC_FAULT);
+    fault::register(&phy::ALLOC_PAGES_FAULT);
 }
-pub fn kernel_end_lma() -> Addr<UMASpace> { kernel_start_lma().byte_add(kernel_size()) }
-pub fn kernel_size() -> usize {
-    kernel_end_vma()
-        .addr_sub(kernel_start_vma())
-        .try_into()
-        .expect("kernel_end_vma should be larger than kernel_start_vma")
-}
-
-// ------------ Segmentation stuff -------------
 
-fn init_gdtr() {
-    unsafe { GDT.0[1] = SegmentDesc::code() };
 
-    let gdtr = Gdtr {
-        limit: (Gdt::LEN * size_of::<SegmentDesc>() - 1) as u16,
-        base: &raw const GDT,
-    };
-
-    unsafe {
-        asm!(
-            "lgdt [{gdtr}]",
-            gdtr = in(reg) &gdtr as *const Gdtr
-        )
-    };
-}
+/// Translate kernel virtual address `vaddr` through the current memory map.
+///
+/// Returns `None` if `vaddr` is outside the kernel address spaces or is not
+/// mapped.
+pub fn translate_kernel(vaddr: usize) -> Option<Addr<UMASpace>> {
+    fn translate<V: VirtSpace>(vaddr: usize) -> Option<Addr<UMASpace>> {
+        if !V::RANGE.contains(&vaddr) {
+            return None;
+        }
+        MMU.get()?.map().translate(Addr::<V>::new(vaddr))
+    }
 
-#[repr(C, packed(2))]
-struct Gdtr {
-    limit: u16,
-    base: *const Gdt,
+    translate::<KernelImageSpace>(vaddr)
+        .or_else(|| translate::<PhysicalRemapSpace>(vaddr))
+        .or_else(|| translate::<DataStackSpace>(vaddr))
 }
 
-static mut GDT: Gdt = Gdt([const { SegmentDesc::invalid() }; Gdt::LEN]);
+/// Walk the current memory map along kernel virtual address `vaddr`, calling
+/// `f` on each paging table entry visited.
+///
+/// Returns `false` if `vaddr` is outside the kernel address spaces.
+pub fn walk_kernel(vaddr: usize, mut f: impl FnMut(&WalkEntry)) -> bool {
+    fn walk<V: VirtSpace>(vaddr: usize, f: &mut impl FnMut(&WalkEntry)) -> bool {
+        let Some(mmu) = MMU.get().filter(|_| V::RANGE.contains(&vaddr)) else {
+            return false;
+        };
+        mmu.map().walk(Addr::<V>::new(vaddr), f);
+        true
+    }
 
-#[repr(C, align(8))]
-struct Gdt([SegmentDesc; Self::LEN]);
-impl Gdt {
-    const LEN: usize = 2;
+    walk::<KernelImageSpace>(vaddr, &mut f)
+        || walk::<PhysicalRemapSpace>(vaddr, &mut f)
+        || walk::<DataStackSpace>(vaddr, &mut f)
 }
-#[repr(C, packed)]
-struct SegmentDesc(u64);
-impl SegmentDesc {
-    const DEFAULT_IDXS: Range<usize> = 54..55;
-    const DESC_TYPE_IDXS: Range<usize> = 44..45;
-    const DPL_IDXS: Range<usize> = 45..47;
-    const GRANULARITY_IDXS: Range<usize> = 55..56;
-    const LONG_MODE_IDXS: Range<usize> = 53..54;
-    const P_IDXS: Range<usize> = 47..48;
-    const TYPE_IDXS: Range<usize> = 40..44;
-
-    fn code() -> Self {
-        let mut bits = 0u64;
-        let view = bits.view_bits_mut::<Lsb0>();
-        view[Self::TYPE_IDXS].store_le(0b1000);
-        view[Self::DESC_TYPE_IDXS].store_le(1);
-        view[Self::DPL_IDXS].store_le(Privilege::Kernel as u8);
-        view[Self::P_IDXS].store_le(1);
-        view[Self::LONG_MODE_IDXS].store(1);
-        Self(bits)
-    }
 
-    const fn invalid() -> Self { Self(0) }
+pub const fn kernel_offset_vma() -> usize { KERNEL_OFFSET_VMA }
+pub fn kernel_start_vma() -> Addr<KernelImageSpace> { layout::kernel().start() }
+pub fn kernel_end_vma() -> Addr<KernelImageSpace> { layout::kernel().end() }
+pub fn kernel_start_lma() -> Addr<UMASpace> { layout::kernel_start_lma() }
+pub fn kernel_end_lma() -> Addr<UMASpace> { kernel_start_lma().byte_add(kernel_size()) }
+pub fn kernel_size() -> usize { layout::kernel().size }
+
+/// Check the layout assumptions of the virtual spaces and allocators.
+///
+/// # Panics
+/// Panics on the first violated assumption.
+pub fn self_check() {
+    virt::self_check();
+    let kernel = layout::kernel();
+    assert!(
+        KernelImageSpace::RANGE.contains(&kernel.start().usize())
+            && kernel.end().usize() <= KernelImageSpace::RANGE.end,
+        "kernel image should lie within KernelImageSpace"
+    );
+    alloc::slab_self_check();
 }
@@ -7,6 +7,7 @@ use strum::VariantArray;
 
 use super::virt::VirtSpace;
 use crate::common::{GiB, KiB, MiB};
+use crate::error::KResult;
 
 /// An address space with constant evaluated address bounds. All addresses are
 /// within an address space, and addresses derived from address operations will
@@ -395,8 +396,11 @@ pub unsafe trait Allocator<S: AddrSpace> {
     /// Attempts to allocate a block. On success, returns an address
     /// range that meet the size and alignment guarentee of layout.
     ///
+    /// Fails with [`KError::NoMem`][crate::error::KError::NoMem] if the space
+    /// is exhausted.
+    ///
     /// See [allocate][core::alloc::Allocator::allocate] for more details.
-    fn allocate(&self, layout: Layout) -> Option<AddrRange<S>>;
+    fn allocate(&self, layout: Layout) -> KResult<AddrRange<S>>;
 
     /// Deallocate the block starting at `addr`.
     ///
@@ -410,7 +414,7 @@ pub unsafe trait Allocator<S: AddrSpace> {
 }
 
 unsafe impl<S: AddrSpace, A: Allocator<S>> Allocator<S> for &A {
-    fn allocate(&self, layout: Layout) -> Option<AddrRange<S>> { (*self).allocate(layout) }
+    fn allocate(&self, layout: Layout) -> KResult<AddrRange<S>> { (*self).allocate(layout) }
 
     unsafe fn deallocate(&self, addr: Addr<S>, layout: Layout) {
         unsafe { (*self).deallocate(addr, layout) }
@@ -1,31 +1,85 @@
 use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+use core::fmt::Write as _;
 use core::ptr::{self, NonNull};
 
+use arrayvec::ArrayString;
+
 use super::addr::{PageAddr, PageSize};
 use super::paging::{Flag, MemoryManager};
 use super::phy::PhySpace;
 use super::virt::VirtSpace;
 use super::UMASpace;
+use crate::boot::cmdline;
+use crate::fault::FaultSite;
+use crate::flight::{self, EventKind};
+use crate::phase::{self, Phase};
+use crate::stats::Stat;
 
 mod page;
 mod slab;
 
 pub use page::PageAllocator;
 pub use slab::SlabAllocator;
+pub(super) use slab::{self_check as slab_self_check, SLAB_CAP_STAT, SLAB_SHRINKER};
+
+pub(super) static ALLOC_STAT: Stat = Stat::counter("mem.allocs");
+pub(super) static DEALLOC_STAT: Stat = Stat::counter("mem.deallocs");
+pub(super) static ALLOC_FAULT: FaultSite = FaultSite::new("mem.alloc");
+
+/// Apply the slab caps given on the command line.
+///
+/// `slab.cap=PAGES` caps every size class, and `slab.cap.SIZE=PAGES` caps
+/// the class serving `SIZE` byte allocations. With `slab.cap_fail`, an
+/// allocation over the cap fails instead of taking its own page.
+pub(super) fn init_slab_caps() {
+    let default_cap = cmdline::parse::<usize>("slab.cap");
+    for order in SlabAllocator::MIN_ORDER..=SlabAllocator::MAX_ORDER {
+        let size = 1 << order;
+        let mut key = ArrayString::<16>::new();
+        write!(key, "slab.cap.{}", size).expect("slab cap key should fit");
+        let cap = cmdline::parse::<usize>(&key).or(default_cap);
+        if cap.is_some() {
+            SlabAllocator::set_cap(size, cap);
+        }
+    }
+    SlabAllocator::set_fail_over_cap(cmdline::has("slab.cap_fail"));
+}
 
 /// The global allocator.
+///
+/// ```kexample
+/// use core::alloc::{Allocator, Layout};
+///
+/// let layout = Layout::new::<[u64; 4]>();
+/// let ptr = crate::mem::GlobalAllocator.allocate(layout).expect("memory should be available");
+/// // SAFETY: `ptr` is allocated above with `layout`.
+/// unsafe { crate::mem::GlobalAllocator.deallocate(ptr.cast(), layout) };
+/// ```
 #[derive(Debug, Clone, Copy)]
 pub struct GlobalAllocator;
 unsafe impl Allocator for GlobalAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        if layout.pad_to_align().size() <= SlabAllocator::MAX_SIZE {
+        phase::require(Phase::Mem);
+        ALLOC_STAT.inc();
+        if ALLOC_FAULT.should_fail() {
+            return Err(AllocError);
+        }
+        let res = if layout.pad_to_align().size() <= SlabAllocator::MAX_SIZE {
             SlabAllocator.allocate(layout)
         } else {
             PageAllocator.allocate(layout)
+        };
+        if res.is_err() {
+            flight::record(
+                EventKind::AllocFail,
+                layout.size() as u64,
+            );
         }
+        res
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        DEALLOC_STAT.inc();
         if layout.pad_to_align().size() <= SlabAllocator::MAX_SIZE {
             unsafe { SlabAllocator.deallocate(ptr, layout) }
         } else {
@@ -4,6 +4,7 @@ use core::fmt::Write as _;
 use core::ops::Div as _;
 use core::ptr::NonNull;
 
+use crate::error::KResult;
 use crate::mem::addr::{self, Addr, AddrRange, AddrSpace, PageRange, PageSize};
 use crate::mem::alloc::{allocate_if_zst, deallocate_if_zst};
 use crate::mem::phy::PhysicalMemoryManager;
@@ -18,9 +19,9 @@ use crate::mem::UMASpace;
 #[derive(Debug, Clone, Copy)]
 pub struct PageAllocator;
 unsafe impl addr::Allocator<UMASpace> for PageAllocator {
-    fn allocate(&self, layout: Layout) -> Option<AddrRange<UMASpace>> {
+    fn allocate(&self, layout: Layout) -> KResult<AddrRange<UMASpace>> {
         if layout.size() == 0 {
-            return Some(AddrRange::empty());
+            return Ok(AddrRange::empty());
         }
 
         debug_assert!(PageSize::MIN.align() % layout.align() == 0);
@@ -34,7 +35,7 @@ unsafe impl addr::Allocator<UMASpace> for PageAllocator {
         debug_assert!(prange.len >= page_cnt);
         debug_assert!(prange.page_size() >= page_size);
 
-        Some(prange.into())
+        Ok(prange.into())
     }
 
     unsafe fn deallocate(&self, addr: Addr<UMASpace>, layout: Layout) {
@@ -62,7 +63,8 @@ unsafe impl Allocator for PageAllocator {
             return Ok(ptr);
         }
 
-        let phy = <Self as addr::Allocator<UMASpace>>::allocate(self, layout).ok_or(AllocError)?;
+        let phy =
+            <Self as addr::Allocator<UMASpace>>::allocate(self, layout).map_err(|_| AllocError)?;
         let base = unsafe { NonNull::new_unchecked(PhysicalRemapSpace::p2v(phy.base).into_ptr()) };
         Ok(NonNull::slice_from_raw_parts(
             base, phy.size,
@@ -106,7 +108,7 @@ unsafe impl Allocator for PhysicalPageManager {
 
         let prange = PhysicalMemoryManager
             .allocate_pages(page_cnt, page_size)
-            .ok_or(AllocError)?;
+            .map_err(|_| AllocError)?;
         debug_assert!(prange.len >= page_cnt);
         debug_assert!(prange.page_size() >= page_size);
 
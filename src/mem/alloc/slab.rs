@@ -5,6 +5,7 @@ use core::cell::UnsafeCell;
 use core::marker::PhantomData;
 use core::mem::{offset_of, transmute, MaybeUninit};
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::{array, slice};
 
 use bitvec::order::Lsb0;
@@ -18,6 +19,21 @@ use super::page::PageAllocator;
 use super::{allocate_if_zst, deallocate_if_zst};
 use crate::common::ll::{self, BoxLinkedListExt as _, LinkedList};
 use crate::mem::addr::PageSize;
+use crate::mem::pressure::Shrinker;
+use crate::stats::Stat;
+
+/// Whether a size class at its cap fails allocations instead of serving them
+/// from the page allocator.
+static FAIL_OVER_CAP: AtomicBool = AtomicBool::new(false);
+
+pub(in crate::mem) static SLAB_CAP_STAT: Stat = Stat::counter("mem.slab_cap_hits");
+
+/// Releases empty slabs under memory pressure.
+pub(in crate::mem) static SLAB_SHRINKER: Shrinker = Shrinker {
+    name: "slab",
+    priority: 0,
+    reclaim: reclaim_empty_slabs,
+};
 
 pub struct SlabAllocator;
 unsafe impl Allocator for SlabAllocator {
@@ -33,6 +49,26 @@ impl SlabAllocator {
     pub const MAX_ORDER: u8 = 10;
     pub const MAX_SIZE: usize = 1 << Self::MAX_ORDER as usize;
     pub const MIN_ORDER: u8 = 3;
+
+    /// Limit the size class serving `size` byte allocations to pinning
+    /// `pages` slab pages. `None` lifts the cap.
+    ///
+    /// Pages already pinned over the cap are kept until their slabs are empty
+    /// and reclaimed under memory pressure.
+    ///
+    /// # Panics
+    /// Panics if `size` is larger than [`Self::MAX_SIZE`].
+    pub fn set_cap(size: usize, pages: Option<usize>) {
+        assert!(size <= Self::MAX_SIZE);
+        let idx = (slot_order(size) - Self::MIN_ORDER) as usize;
+        SLAB_ALLOCATOR_RECORD.caches[idx].lock().cap = pages.unwrap_or(usize::MAX);
+    }
+
+    /// Set whether a size class at its cap fails allocations with
+    /// [`AllocError`], instead of serving each from its own page.
+    pub fn set_fail_over_cap(is_failing: bool) {
+        FAIL_OVER_CAP.store(is_failing, Ordering::Relaxed)
+    }
 }
 static SLAB_ALLOCATOR_RECORD: spin::Lazy<SlabAllocatorRecord> =
     spin::Lazy::new(|| SlabAllocatorRecord {
@@ -49,14 +85,20 @@ unsafe impl Allocator for SlabAllocatorRecord {
             return Ok(ptr);
         }
 
-        let slot_order = layout
-            .pad_to_align()
-            .size()
-            .next_multiple_of(1 << SlabAllocator::MIN_ORDER)
-            .next_power_of_two()
-            .ilog2() as u8;
+        let slot_order = slot_order(layout.pad_to_align().size());
         let mut cache = self.caches[(slot_order - SlabAllocator::MIN_ORDER) as usize].lock();
 
+        if cache.is_at_cap() {
+            SLAB_CAP_STAT.inc();
+            if FAIL_OVER_CAP.load(Ordering::Relaxed) {
+                return Err(AllocError);
+            }
+            // A page-aligned pointer cannot come from a slab, whose header
+            // is at the start of the page, so `deallocate` can tell the two
+            // apart.
+            return PageAllocator.allocate(OVER_CAP_LAYOUT);
+        }
+
         // TODO: Refactor this shit
         // SAFETY: Cache for order i is always located at index i
         unsafe {
@@ -81,12 +123,12 @@ unsafe impl Allocator for SlabAllocatorRecord {
         if deallocate_if_zst(ptr, layout) {
             return;
         }
-        let slot_order = layout
-            .pad_to_align()
-            .size()
-            .next_multiple_of(1 << SlabAllocator::MIN_ORDER)
-            .next_power_of_two()
-            .ilog2() as u8;
+        if ptr.as_ptr() as usize % SLAB_PAGE.align() == 0 {
+            // SAFETY: Only allocations over the cap are page-aligned.
+            unsafe { PageAllocator.deallocate(ptr, OVER_CAP_LAYOUT) };
+            return;
+        }
+        let slot_order = slot_order(layout.pad_to_align().size());
         let mut cache = self.caches[(slot_order - SlabAllocator::MIN_ORDER) as usize].lock();
         // TODO: Refactor this shit
         // SAFETY: Cache for order i is always located at index i
@@ -111,6 +153,35 @@ unsafe impl Allocator for SlabAllocatorRecord {
 impl SlabAllocatorRecord {
     const CACHES_CNT: usize = (SlabAllocator::MAX_ORDER - SlabAllocator::MIN_ORDER + 1) as usize;
 }
+
+/// Layout of an allocation served by the page allocator over the cap.
+// SAFETY: A page size is a power of two.
+const OVER_CAP_LAYOUT: Layout =
+    unsafe { Layout::from_size_align_unchecked(SLAB_PAGE.usize(), SLAB_PAGE.align()) };
+
+/// Release up to `frames` empty slabs, skipping caches in use.
+fn reclaim_empty_slabs(frames: usize) -> usize {
+    let mut freed = 0;
+    for cache in &SLAB_ALLOCATOR_RECORD.caches {
+        // The allocation under pressure may come from a cache holding its
+        // lock.
+        let Some(mut cache) = cache.try_lock() else {
+            continue;
+        };
+        while freed < frames && cache.empty_slabs.pop_front().is_some() {
+            cache.slab_cnt -= 1;
+            freed += 1;
+        }
+    }
+    freed
+}
+
+/// Returns the order of the slot size serving a `size` byte allocation.
+fn slot_order(size: usize) -> u8 {
+    size.next_multiple_of(1 << SlabAllocator::MIN_ORDER)
+        .next_power_of_two()
+        .ilog2() as u8
+}
 impl<const N: usize> Item for [u8; N] {
     const LAYOUT: Layout = {
         assert!(N != 0);
@@ -126,6 +197,10 @@ pub struct UntypedCache {
     empty_slabs: LinkedList<SLAB_LINK_OFFSET, BoxSlab>,
     partial_slabs: LinkedList<SLAB_LINK_OFFSET, BoxSlab>,
     full_slabs: LinkedList<SLAB_LINK_OFFSET, BoxSlab>,
+    /// Number of slab pages pinned by the cache.
+    slab_cnt: usize,
+    /// Most slab pages the cache may pin.
+    cap: usize,
 }
 impl UntypedCache {
     /// Creates a type-erased cache.
@@ -137,9 +212,16 @@ impl UntypedCache {
             empty_slabs: LinkedList::<SLAB_LINK_OFFSET, BoxSlab>::new_in(PageAllocator),
             partial_slabs: LinkedList::<SLAB_LINK_OFFSET, BoxSlab>::new_in(PageAllocator),
             full_slabs: LinkedList::<SLAB_LINK_OFFSET, BoxSlab>::new_in(PageAllocator),
+            slab_cnt: 0,
+            cap: usize::MAX,
         }
     }
 
+    /// Check if every slab is full and no more slab may be added.
+    fn is_at_cap(&self) -> bool {
+        self.slab_cnt >= self.cap && self.partial_slabs.is_empty() && self.empty_slabs.is_empty()
+    }
+
     /// Get a typed view into the `self`
     ///
     /// # Safety
@@ -190,6 +272,7 @@ impl<T: Item> Cache<T> {
             unsafe { Slab::<T>::new_unsafe_cell().__init(box_slab.as_mut_ptr().cast()) }.ok()?;
             // SAFETY: Initialized above.
             slab_cursor.insert_after(unsafe { box_slab.assume_init() });
+            self.inner.slab_cnt += 1;
             slab_cursor.move_next();
         }
 
@@ -444,6 +527,29 @@ impl<T: Item> Slab<T> {
     }
 }
 
+/// Check the layout assumptions of slabs.
+///
+/// # Panics
+/// Panics on the first violated assumption.
+pub(in crate::mem) fn self_check() {
+    assert!(
+        size_of::<UntypedSlab>() <= SLAB_PAGE.usize(),
+        "UntypedSlab should fit into a SLAB_PAGE"
+    );
+    assert!(
+        SLAB_PAGE.align() == SLAB_PAGE.usize(),
+        "slab headers are found by masking, so SLAB_PAGE should be aligned to its size"
+    );
+    assert!(
+        offset_of!(UntypedSlab, buf) != 0,
+        "over cap pages are told apart by alignment, so slots should never be page aligned"
+    );
+    assert!(
+        SlabAllocator::MAX_SIZE <= SLAB_BUF_SIZE,
+        "a slab should hold at least one slot of SlabAllocator::MAX_SIZE"
+    );
+}
+
 pub trait Item: Sized {
     const LAYOUT: Layout = Layout::new::<Self>();
     const _ASSERT_ITEM_IS_ALIGNED_TO_SLAB_PAGE: () =
@@ -0,0 +1,252 @@
+//! Mapping kernel buffers for device DMA.
+//!
+//! A device may not reach every frame, e.g. a 32-bit only device on a machine
+//! with RAM above 4 GiB. Buffers which the device cannot reach, or which are
+//! not physically contiguous, are bounced through frames it can reach. The
+//! bounce frames are filled on map for writes to the device, and copied back
+//! on unmap for reads from the device.
+//!
+//! Bounce frames are taken from the frame allocator if the device reaches
+//! them, and otherwise from a pool reserved at the start of the lowest free
+//! memory at boot.
+
+use core::ptr::{self, NonNull};
+
+use super::addr::{Addr, PageAddr, PageRange, PageSize};
+use super::phy::{BootMemoryManager, PhysicalMemoryManager, FRAME_SIZE};
+use super::virt::PhysicalRemapSpace;
+use super::{translate_kernel, UMASpace};
+use crate::error::{KError, KResult};
+use crate::klog;
+
+/// Number of frames in the bounce pool.
+const BOUNCE_POOL_LEN: usize = u64::BITS as usize;
+
+static BOUNCE_POOL: spin::Once<spin::Mutex<BouncePool>> = spin::Once::new();
+
+/// Frames below the frame allocator reserved for bouncing.
+struct BouncePool {
+    base: Addr<UMASpace>,
+    /// Bitmap of allocated frames.
+    used: u64,
+}
+impl BouncePool {
+    fn allocate(&mut self, cnt: usize) -> Option<PageRange<UMASpace>> {
+        if cnt > BOUNCE_POOL_LEN {
+            return None;
+        }
+        let run = u64::MAX >> (BOUNCE_POOL_LEN - cnt);
+        let idx = (0..=BOUNCE_POOL_LEN - cnt).find(|idx| self.used & (run << idx) == 0)?;
+        self.used |= run << idx;
+        Some(PageRange {
+            base: PageAddr::new(
+                self.base.byte_add(idx * FRAME_SIZE),
+                PageSize::MIN,
+            ),
+            len: cnt,
+        })
+    }
+
+    /// Returns whether `range` belongs to the pool, freeing it if so.
+    fn deallocate(&mut self, range: PageRange<UMASpace>) -> bool {
+        let offset = range.base.addr().addr_sub(self.base);
+        if offset < 0 || offset as usize >= BOUNCE_POOL_LEN * FRAME_SIZE {
+            return false;
+        }
+        let run = u64::MAX >> (BOUNCE_POOL_LEN - range.len);
+        self.used &= !(run << (offset as usize / FRAME_SIZE));
+        true
+    }
+}
+
+/// Reserve the bounce pool from the lowest free memory.
+///
+/// Should be called before `bmm` allocates, see
+/// [`BootMemoryManager::reserve_range`].
+pub(super) fn init(bmm: &BootMemoryManager) {
+    let Some(range) = bmm.reserve_lowest(BOUNCE_POOL_LEN * FRAME_SIZE) else {
+        klog!(Warn, "dma: no memory for bounce pool");
+        return;
+    };
+    BOUNCE_POOL.call_once(|| {
+        spin::Mutex::new(BouncePool {
+            base: range.base,
+            used: 0,
+        })
+    });
+}
+
+/// Physical addresses a device can reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaMask(u64);
+impl DmaMask {
+    /// ISA DMA.
+    pub const BITS_24: Self = Self::bits(24);
+    pub const BITS_32: Self = Self::bits(32);
+    pub const BITS_64: Self = Self::bits(64);
+
+    /// Creates a mask for devices reaching up to and including `last`.
+    pub const fn limit(last: u64) -> Self { Self(last) }
+
+    /// Creates a mask for devices addressing `bits` bits.
+    pub const fn bits(bits: u32) -> Self {
+        if bits >= 64 {
+            Self(u64::MAX)
+        } else {
+            Self((1 << bits) - 1)
+        }
+    }
+
+    /// Returns whether the device can reach `len` bytes from `base`.
+    fn reaches(self, base: Addr<UMASpace>, len: usize) -> bool {
+        let Some(last) = (base.usize() as u64).checked_add(len.max(1) as u64 - 1) else {
+            return false;
+        };
+        last <= self.0
+    }
+}
+
+/// Direction of a DMA transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDir {
+    ToDevice,
+    FromDevice,
+    Bidirectional,
+}
+
+/// A buffer mapped for DMA by [`dma_map_single`].
+///
+/// Dropping the mapping unmaps it, see [`dma_unmap`].
+#[derive(Debug)]
+pub struct DmaMapping {
+    dev_addr: Addr<UMASpace>,
+    buf: NonNull<[u8]>,
+    dir: DmaDir,
+    bounce: Option<PageRange<UMASpace>>,
+}
+impl DmaMapping {
+    /// Returns the address the device should access.
+    pub fn dev_addr(&self) -> u64 { self.dev_addr.usize() as u64 }
+
+    pub fn len(&self) -> usize { self.buf.len() }
+
+    pub fn is_empty(&self) -> bool { self.buf.is_empty() }
+
+    /// Returns whether the buffer is bounced.
+    pub fn is_bounced(&self) -> bool { self.bounce.is_some() }
+}
+impl Drop for DmaMapping {
+    fn drop(&mut self) {
+        let Some(bounce) = self.bounce else {
+            return;
+        };
+        if self.dir != DmaDir::ToDevice {
+            let src = PhysicalRemapSpace::p2v(self.dev_addr).into_ptr::<u8>();
+            // SAFETY: The bounce frames hold the buffer length, and the
+            // buffer is valid until unmapped as required by dma_map_single.
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    src,
+                    self.buf.cast().as_ptr(),
+                    self.buf.len(),
+                )
+            };
+        }
+        // SAFETY: The bounce frames are allocated in dma_map_single and no
+        // longer accessed by the device.
+        unsafe { free_bounce(bounce) };
+    }
+}
+
+/// Map the kernel buffer `buf` for DMA by a device reaching `mask`.
+///
+/// Fails with [`KError::Fault`] if `buf` is not mapped, or [`KError::NoMem`]
+/// if no bounce frames the device can reach are free.
+///
+/// # Safety
+/// `buf` should stay valid and not be accessed by the CPU until the mapping is
+/// unmapped.
+pub unsafe fn dma_map_single(
+    buf: NonNull<[u8]>,
+    mask: DmaMask,
+    dir: DmaDir,
+) -> KResult<DmaMapping> {
+    let vbase = buf.cast::<u8>().as_ptr() as usize;
+    let pbase = translate_kernel(vbase).ok_or(KError::Fault)?;
+    if is_contiguous(vbase, pbase, buf.len())? && mask.reaches(pbase, buf.len()) {
+        return Ok(DmaMapping {
+            dev_addr: pbase,
+            buf,
+            dir,
+            bounce: None,
+        });
+    }
+
+    let bounce = alloc_bounce(buf.len(), mask)?;
+    let bounce_base = bounce.base.addr();
+    if dir != DmaDir::FromDevice {
+        let dst = PhysicalRemapSpace::p2v(bounce_base).into_ptr::<u8>();
+        // SAFETY: The bounce frames hold the buffer length, and buf is valid
+        // as guaranteed by caller.
+        unsafe { ptr::copy_nonoverlapping(buf.cast().as_ptr(), dst, buf.len()) };
+    }
+    Ok(DmaMapping {
+        dev_addr: bounce_base,
+        buf,
+        dir,
+        bounce: Some(bounce),
+    })
+}
+
+/// Unmap `mapping`, copying a bounced buffer back if the device wrote to it.
+pub fn dma_unmap(mapping: DmaMapping) { drop(mapping) }
+
+/// Allocate bounce frames holding `len` bytes which the device can reach,
+/// falling back to the bounce pool.
+fn alloc_bounce(len: usize, mask: DmaMask) -> KResult<PageRange<UMASpace>> {
+    let page_cnt = len.div_ceil(FRAME_SIZE).max(1);
+    if let Ok(bounce) = PhysicalMemoryManager.allocate_pages(page_cnt, PageSize::MIN) {
+        if mask.reaches(bounce.base.addr(), len) {
+            return Ok(bounce);
+        }
+        // SAFETY: The frames are allocated above and never handed out.
+        unsafe { PhysicalMemoryManager.deallocate_pages(bounce) };
+    }
+    let pool = BOUNCE_POOL.get().ok_or(KError::NoMem)?;
+    let mut pool = pool.lock();
+    let bounce = pool.allocate(page_cnt).ok_or(KError::NoMem)?;
+    if !mask.reaches(bounce.base.addr(), len) {
+        pool.deallocate(bounce);
+        return Err(KError::NoMem);
+    }
+    Ok(bounce)
+}
+
+/// Free bounce frames allocated by [`alloc_bounce`].
+///
+/// # Safety
+/// `bounce` should be allocated by [`alloc_bounce`] and no longer accessed.
+unsafe fn free_bounce(bounce: PageRange<UMASpace>) {
+    if let Some(pool) = BOUNCE_POOL.get() {
+        if pool.lock().deallocate(bounce) {
+            return;
+        }
+    }
+    // SAFETY: bounce is allocated from the frame allocator as guaranteed by
+    // caller.
+    unsafe { PhysicalMemoryManager.deallocate_pages(bounce) };
+}
+
+/// Returns whether `len` bytes from `vbase` map to contiguous frames from
+/// `pbase`.
+fn is_contiguous(vbase: usize, pbase: Addr<UMASpace>, len: usize) -> KResult<bool> {
+    let mut offset = FRAME_SIZE - vbase % FRAME_SIZE;
+    while offset < len {
+        let paddr = translate_kernel(vbase + offset).ok_or(KError::Fault)?;
+        if paddr != pbase.byte_add(offset) {
+            return Ok(false);
+        }
+        offset += FRAME_SIZE;
+    }
+    Ok(true)
+}
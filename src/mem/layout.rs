@@ -0,0 +1,91 @@
+//! Kernel image layout, as placed by `link.ld`.
+//!
+//! Every section but `.ex_table` is page aligned, and all lie within
+//! [`KernelImageSpace`].
+
+use super::addr::{Addr, AddrRange, AddrSpace as _};
+use super::virt::KernelImageSpace;
+use super::UMASpace;
+
+unsafe extern "C" {
+    static _KERNEL_START_LMA: u8;
+    static _KERNEL_START_VMA: u8;
+    static _KERNEL_END_VMA: u8;
+    static _TEXT_START_VMA: u8;
+    static _TEXT_END_VMA: u8;
+    static _RODATA_START_VMA: u8;
+    static _RODATA_END_VMA: u8;
+    static _EX_TABLE_START_VMA: u8;
+    static _EX_TABLE_END_VMA: u8;
+    static _DATA_START_VMA: u8;
+    static _DATA_END_VMA: u8;
+    static _BSS_START_VMA: u8;
+    static _BSS_END_VMA: u8;
+}
+
+/// Returns the whole kernel image.
+pub fn kernel() -> AddrRange<KernelImageSpace> {
+    section(
+        &raw const _KERNEL_START_VMA,
+        &raw const _KERNEL_END_VMA,
+    )
+}
+
+/// Returns the `.text` section.
+pub fn text() -> AddrRange<KernelImageSpace> {
+    section(
+        &raw const _TEXT_START_VMA,
+        &raw const _TEXT_END_VMA,
+    )
+}
+
+/// Returns the `.rodata` section.
+pub fn rodata() -> AddrRange<KernelImageSpace> {
+    section(
+        &raw const _RODATA_START_VMA,
+        &raw const _RODATA_END_VMA,
+    )
+}
+
+/// Returns the `.ex_table` section, holding the exception fixup table.
+pub fn ex_table() -> AddrRange<KernelImageSpace> {
+    section(
+        &raw const _EX_TABLE_START_VMA,
+        &raw const _EX_TABLE_END_VMA,
+    )
+}
+
+/// Returns the `.data` section.
+pub fn data() -> AddrRange<KernelImageSpace> {
+    section(
+        &raw const _DATA_START_VMA,
+        &raw const _DATA_END_VMA,
+    )
+}
+
+/// Returns the `.bss` section.
+pub fn bss() -> AddrRange<KernelImageSpace> {
+    section(
+        &raw const _BSS_START_VMA,
+        &raw const _BSS_END_VMA,
+    )
+}
+
+/// Returns the physical address the kernel image is loaded at.
+pub fn kernel_start_lma() -> Addr<UMASpace> {
+    // The kernel is loaded during real mode at the actual physical address.
+    Addr::new(&raw const _KERNEL_START_LMA as usize)
+}
+
+/// # Panics
+/// Panics if the section is not within [`KernelImageSpace`].
+fn section(start: *const u8, end: *const u8) -> AddrRange<KernelImageSpace> {
+    let (start, end) = (start as usize, end as usize);
+    assert!(
+        KernelImageSpace::RANGE.start <= start
+            && start <= end
+            && end <= KernelImageSpace::RANGE.end,
+        "kernel section should be within KernelImageSpace"
+    );
+    AddrRange::new(Addr::new(start), end - start)
+}
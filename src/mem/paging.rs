@@ -6,6 +6,7 @@ use core::alloc::{Allocator, Layout};
 use core::arch::asm;
 use core::cell::SyncUnsafeCell;
 use core::fmt::Write as _;
+use core::marker::PhantomData;
 use core::ops::{DerefMut, Range};
 use core::ptr::{self, NonNull};
 use core::sync::atomic::AtomicBool;
@@ -19,6 +20,8 @@ use super::phy::BootMemoryManager;
 use super::virt::{PhysicalRemapSpace, RecursivePagingSpace, VirtSpace};
 use super::{PageAllocator, UMASpace};
 use crate::common::hlt;
+use crate::common::lock::Mutex;
+use crate::error::KResult;
 use crate::mem::addr::AddrSpace;
 use crate::mem::virt::{DataStackSpace, KernelImageSpace};
 use crate::mem::{kernel_end_vma, kernel_size};
@@ -56,6 +59,8 @@ pub trait MemoryMap {
     /// - Physical memory page of size `page_size` pointed by `paddr` does not
     /// contain any live reference or owned values.
     ///
+    /// Fails if a page table cannot be allocated from `alloc`.
+    ///
     /// # Panics
     /// - `page_size` should be supported by the `MemoryManager`
     unsafe fn map<V: VirtSpace, const N: usize>(
@@ -64,7 +69,7 @@ pub trait MemoryMap {
         ppage: PageAddr<UMASpace>,
         flags: [Flag; N],
         alloc: &mut impl addr::Allocator<UMASpace>,
-    ) -> Option<()>;
+    ) -> KResult<()>;
 
     /// Removes mapping at `vaddr`.
     ///
@@ -80,13 +85,61 @@ pub trait MemoryMap {
     /// Try translating a virtual address into a physical address. Fails iff
     /// the virtual address is not mapped.
     fn translate<V: VirtSpace>(&mut self, vaddr: Addr<V>) -> Option<Addr<UMASpace>>;
+
+    /// Walk the paging tables along `vaddr` from the top, calling `f` on each
+    /// entry visited by [`Self::translate`].
+    fn walk<V: VirtSpace>(&mut self, vaddr: Addr<V>, f: impl FnMut(&WalkEntry));
+}
+
+/// A paging table entry visited by [`MemoryMap::walk`].
+pub struct WalkEntry {
+    level: Level,
+    raw: RawEntry,
+}
+impl WalkEntry {
+    fn new(entry: &EntryRef<'_>) -> Self {
+        Self {
+            level: entry.level(),
+            raw: entry.raw_value(),
+        }
+    }
+
+    pub fn level_name(&self) -> &'static str {
+        match self.level {
+            Level::CR3 => "CR3",
+            Level::PML4 => "PML4",
+            Level::PDPT => "PDPT",
+            Level::PD => "PD",
+            Level::PT => "PT",
+        }
+    }
+
+    pub fn raw(&self) -> usize { self.raw.0 }
+
+    /// Returns whether `flag` is set, or `None` if the entry cannot have it.
+    pub fn flag(&self, flag: Flag) -> Option<bool> {
+        let mut raw = self.raw;
+        // SAFETY: The entry was read at `level`.
+        unsafe { EntryRef::from_raw(&mut raw, self.level) }.flag(flag)
+    }
+
+    /// Returns the size and physical base of the page this entry maps, if it
+    /// maps a page.
+    pub fn page(&self) -> Option<(PageSize, Addr<UMASpace>)> {
+        let mut raw = self.raw;
+        // SAFETY: The entry was read at `level`.
+        match unsafe { EntryRef::from_raw(&mut raw, self.level) }.target() {
+            EntryTarget::Page(level, addr) => Some((level.page_size(), addr)),
+            _ => None,
+        }
+    }
 }
 
 //---------------------------- x86-64 stuff below ---------------------------//
 
 pub static MMU: spin::Once<X86_64MemoryManager> = spin::Once::new();
 // TODO: Use RAII to guard kernel mappings.
-pub static KERNEL_MAP_LOCK: spin::Mutex<()> = spin::Mutex::new(());
+pub static KERNEL_MAP_LOCK: Mutex<()> = Mutex::new("mem.kernel_map", ());
 
 const DEFAULT_PAGE_TABLE_FLAGS: [Flag; 2] = [Flag::Present, Flag::ReadWrite];
 
@@ -277,6 +330,17 @@ impl X86_64MemoryMap {
             .expect("Flags should be valid");
         Self { cr3 }
     }
+
+    /// Borrow `self` for read-only inspection.
+    ///
+    /// The map need not be active, since its tables are reached through the
+    /// physical remap.
+    pub fn view(&self) -> MapView<'_> {
+        MapView {
+            cr3: self.cr3,
+            _map: PhantomData,
+        }
+    }
 }
 impl MemoryMap for X86_64MemoryMap {
     unsafe fn map<V: VirtSpace, const N: usize>(
@@ -285,7 +349,7 @@ impl MemoryMap for X86_64MemoryMap {
         ppage: PageAddr<UMASpace>,
         flags: [Flag; N],
         allocator: &mut impl addr::Allocator<UMASpace>,
-    ) -> Option<()> {
+    ) -> KResult<()> {
         debug_assert!(vpage.page_size() == ppage.page_size());
         let mut _kernel_map_guard = None;
         if V::IS_KERNEL {
@@ -298,32 +362,66 @@ impl MemoryMap for X86_64MemoryMap {
         let target_level = Level::from_page_size(vpage.page_size());
 
         while cur_level != target_level {
-            walker.down(allocator);
+            walker.down(allocator)?;
             cur_level = walker.cur().level();
         }
 
         unsafe { walker.cur().reinit(ppage.start(), flags) };
-        Some(())
+        Ok(())
     }
 
 
     unsafe fn unmap<V: VirtSpace>(&mut self, vaddr: Addr<V>) { todo!() }
 
     fn translate<V: VirtSpace>(&mut self, vaddr: Addr<V>) -> Option<Addr<UMASpace>> {
-        let mut _kernel_map_guard = None;
-        if V::IS_KERNEL {
-            _kernel_map_guard = Some(KERNEL_MAP_LOCK.lock());
-        }
+        let mut paddr = None;
+        self.walk(vaddr, |entry| {
+            paddr = entry.page().map(|(_, addr)| addr);
+        });
+        paddr
+    }
 
-        let mut walker = unsafe { LinearWalker::new(self.into(), vaddr) };
+    fn walk<V: VirtSpace>(&mut self, vaddr: Addr<V>, f: impl FnMut(&WalkEntry)) {
+        walk_from(&mut self.cr3, vaddr, f)
+    }
+}
 
-        while walker.try_down().is_some() {}
+/// A read-only view of a [`X86_64MemoryMap`], which may be another task's.
+pub struct MapView<'a> {
+    /// Copy of the map's cr3 entry, so that walking never writes to the map.
+    cr3: RawEntry,
+    _map: PhantomData<&'a X86_64MemoryMap>,
+}
+impl MapView<'_> {
+    /// See [`MemoryMap::translate`].
+    pub fn translate<V: VirtSpace>(&self, vaddr: Addr<V>) -> Option<Addr<UMASpace>> {
+        let mut paddr = None;
+        self.walk(vaddr, |entry| {
+            paddr = entry.page().map(|(_, addr)| addr);
+        });
+        paddr
+    }
 
-        match walker.cur().target() {
-            EntryTarget::None => None,
-            EntryTarget::Page(_, addr) => Some(addr),
-            EntryTarget::Table(..) => unreachable!(),
-        }
+    /// See [`MemoryMap::walk`].
+    pub fn walk<V: VirtSpace>(&self, vaddr: Addr<V>, f: impl FnMut(&WalkEntry)) {
+        let mut cr3 = self.cr3;
+        walk_from(&mut cr3, vaddr, f)
+    }
+}
+
+fn walk_from<V: VirtSpace>(cr3: &mut RawEntry, vaddr: Addr<V>, mut f: impl FnMut(&WalkEntry)) {
+    let mut _kernel_map_guard = None;
+    if V::IS_KERNEL {
+        _kernel_map_guard = Some(KERNEL_MAP_LOCK.lock());
+    }
+
+    // SAFETY: cr3 is the top entry of a memory map.
+    let cr3 = unsafe { EntryRef::from_raw(cr3, Level::CR3) };
+    let mut walker = unsafe { LinearWalker::new(cr3, vaddr) };
+
+    f(&WalkEntry::new(walker.cur()));
+    while let Some(entry) = walker.try_down() {
+        f(&WalkEntry::new(entry));
     }
 }
 impl Drop for X86_64MemoryMap {
@@ -406,16 +504,17 @@ impl<'a, T: VirtSpace> LinearWalker<'a, T> {
     /// Moves walker down.
     ///
     /// If walker is at the last level, do nothing. If next level of walker is
-    /// unmapped, create a new table, and then move down.
-    fn down(&mut self, alloc: &mut impl addr::Allocator<UMASpace>) -> &mut EntryRef<'a> {
+    /// unmapped, create a new table, and then move down. Fails if the new
+    /// table cannot be allocated.
+    fn down(&mut self, alloc: &mut impl addr::Allocator<UMASpace>) -> KResult<&mut EntryRef<'a>> {
         if self.cur_entry.level().next_level().is_none() {
-            return self.cur();
+            return Ok(self.cur());
         }
 
         let target = self.cur_entry.target();
         match target {
             EntryTarget::None | EntryTarget::Page(..) => {
-                let table_paddr = alloc.allocate(PageSize::Small.layout()).unwrap().base;
+                let table_paddr = alloc.allocate(PageSize::Small.layout())?.base;
                 let table_level = self.cur_entry.level().next_level().unwrap();
                 unsafe {
                     self.cur_entry.reinit(
@@ -423,9 +522,9 @@ impl<'a, T: VirtSpace> LinearWalker<'a, T> {
                         DEFAULT_PAGE_TABLE_FLAGS,
                     );
                 }
-                unsafe { self.down_with_table(table_paddr, table_level) }
+                Ok(unsafe { self.down_with_table(table_paddr, table_level) })
             },
-            EntryTarget::Table(level, addr) => unsafe { self.down_with_table(addr, level) },
+            EntryTarget::Table(level, addr) => Ok(unsafe { self.down_with_table(addr, level) }),
         }
     }
 
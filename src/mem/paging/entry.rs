@@ -38,6 +38,8 @@ impl<'a> EntryRef<'a> {
 
     pub fn raw(self) -> &'a mut RawEntry { self.raw }
 
+    pub fn raw_value(&self) -> RawEntry { *self.raw }
+
     /// Get the referenced target for `Entry`
     pub fn target(&self) -> EntryTarget {
         use Level::*;
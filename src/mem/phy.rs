@@ -12,15 +12,20 @@ use memblock::MemblockSystem;
 use multiboot2::{BootInformation, MemoryArea, MemoryAreaTypeId};
 
 use super::addr::{self, Addr, AddrSpace, PageAddr, PageRange, PageSize};
-use super::kernel_start_lma;
 use super::paging::{MemoryManager, MMU};
 use super::virt::PhysicalRemapSpace;
+use super::{kernel_start_lma, pressure};
+use crate::common::lock::{self, Mutex};
 use crate::common::{hlt, TiB};
+use crate::error::{KError, KResult};
+use crate::fault::FaultSite;
 use crate::mem::addr::AddrRange;
 use crate::mem::{kernel_end_lma, paging};
+use crate::stats::Stat;
 
 mod buddy;
 mod memblock;
+mod memtest;
 
 pub fn init_boot_mem(memory_areas: &[MemoryArea]) -> BootMemoryManager {
     BootMemoryManager(RefCell::new(memblock::init(
@@ -32,8 +37,10 @@ pub fn init(mut bmm: BootMemoryManager) {
     PMM.call_once(|| {
         // SAFETY: PhysicalRemap was mapped.
         let pmm = unsafe { PhysicalMemoryRecord::new(&bmm) };
-        spin::Mutex::new(pmm)
+        Mutex::new("mem.pmm", pmm)
     });
+    pressure::init(FREE_FRAMES_STAT.get() as usize);
+    lock::register(PMM.get().expect("PMM should be initialized"));
 }
 
 pub trait PhySpace: AddrSpace {}
@@ -69,7 +76,10 @@ impl Frame {
     }
 }
 
-static PMM: spin::Once<spin::Mutex<PhysicalMemoryRecord>> = spin::Once::new();
+static PMM: spin::Once<Mutex<PhysicalMemoryRecord>> = spin::Once::new();
+pub(super) static ALLOCATED_FRAMES_STAT: Stat = Stat::gauge("mem.allocated_frames");
+pub(super) static FREE_FRAMES_STAT: Stat = Stat::gauge("mem.free_frames");
+pub(super) static ALLOC_PAGES_FAULT: FaultSite = FaultSite::new("mem.alloc_pages");
 pub const FRAME_ORDER: u8 = PageSize::MIN.order();
 pub const FRAME_SIZE: usize = PageSize::MIN.usize();
 
@@ -108,6 +118,8 @@ impl PhysicalMemoryRecord {
             BuddySystem::new(frames.len(), bmm).expect("Boot Allocator should not fail.");
 
         bmm.0.borrow_mut().freeze();
+        // SAFETY: Free memory is not handed out until the buddy is built.
+        unsafe { memtest::run(&mut bmm.0.borrow_mut()) };
         let memblock_system = bmm.0.borrow();
         let free_blocks = memblock_system.free_blocks();
         for free_block in free_blocks {
@@ -124,6 +136,7 @@ impl PhysicalMemoryRecord {
                 unsafe {
                     buddy.free_forced(idx, order);
                 }
+                FREE_FRAMES_STAT.add(1 << order);
             }
         }
         Self {
@@ -133,16 +146,18 @@ impl PhysicalMemoryRecord {
         }
     }
 
-    fn allocate_pages(&mut self, cnt: usize, page_size: PageSize) -> Option<PageRange<UMASpace>> {
+    fn allocate_pages(&mut self, cnt: usize, page_size: PageSize) -> KResult<PageRange<UMASpace>> {
         let frame_cnt = cnt * (page_size.usize() / FRAME_SIZE);
         let allocate_cnt = frame_cnt.next_power_of_two();
         let order = allocate_cnt.ilog2() as u8;
         if order > self.buddy.max_order() {
-            return None;
+            return Err(KError::Inval);
         }
 
-        let frame_idx = self.buddy.reserve(order)?;
+        let frame_idx = self.buddy.reserve(order).ok_or(KError::NoMem)?;
         self.frames[frame_idx].order = order;
+        ALLOCATED_FRAMES_STAT.add(allocate_cnt as u64);
+        FREE_FRAMES_STAT.sub(allocate_cnt as u64);
 
         let base = self
             .base
@@ -151,7 +166,7 @@ impl PhysicalMemoryRecord {
         let base = PageAddr::new(base.addr(), page_size);
 
         let len = allocate_cnt >> (page_size.order() - FRAME_ORDER);
-        Some(PageRange { base, len })
+        Ok(PageRange { base, len })
     }
 
     unsafe fn deallocate_pages(&mut self, pages: PageRange<UMASpace>) {
@@ -164,6 +179,8 @@ impl PhysicalMemoryRecord {
             self.buddy.free(frame_idx, frame_order);
         }
         self.frames[frame_idx].order = 0;
+        ALLOCATED_FRAMES_STAT.sub(1 << frame_order);
+        FREE_FRAMES_STAT.add(1 << frame_order);
     }
 
     fn frame(&self, addr: impl Into<Addr<UMASpace>>) -> Option<&Frame> {
@@ -185,11 +202,33 @@ impl PhysicalMemoryRecord {
 
 pub struct PhysicalMemoryManager;
 impl PhysicalMemoryManager {
-    pub fn allocate_pages(&self, cnt: usize, page_size: PageSize) -> Option<PageRange<UMASpace>> {
+    /// Allocate `cnt` contiguous pages of `page_size`.
+    ///
+    /// Fails with [`KError::Inval`] if the request is larger than the
+    /// largest buddy block, or [`KError::NoMem`] if no such block is free
+    /// even after reclaiming through [`pressure`].
+    pub fn allocate_pages(&self, cnt: usize, page_size: PageSize) -> KResult<PageRange<UMASpace>> {
+        if ALLOC_PAGES_FAULT.should_fail() {
+            return Err(KError::NoMem);
+        }
         // FIXME : Not safe!
-        unsafe { PMM.get_unchecked() }
-            .lock()
-            .allocate_pages(cnt, page_size)
+        let pmm = unsafe { PMM.get_unchecked() };
+        // The lock is released before reclaiming, as shrinkers free frames.
+        let res = pmm.lock().allocate_pages(cnt, page_size);
+        let res = match res {
+            Err(KError::NoMem) => {
+                let frame_cnt = cnt * (page_size.usize() / FRAME_SIZE);
+                if pressure::reclaim(frame_cnt) == 0 {
+                    return Err(KError::NoMem);
+                }
+                pmm.lock().allocate_pages(cnt, page_size)
+            },
+            res => res,
+        };
+        if res.is_ok() {
+            pressure::check(FREE_FRAMES_STAT.get() as usize);
+        }
+        res
     }
 
     pub unsafe fn deallocate_pages(&self, pages: PageRange<UMASpace>) {
@@ -207,12 +246,12 @@ unsafe impl addr::Allocator<UMASpace> for PhysicalMemoryManager {
     /// layout.
     ///
     /// Use [`Self::allocate_pages`] to allocate pages directly.
-    fn allocate(&self, layout: Layout) -> Option<AddrRange<UMASpace>> {
+    fn allocate(&self, layout: Layout) -> KResult<AddrRange<UMASpace>> {
         debug_assert!(
             PMM.get().is_some(),
             "PhysicalMemoryRecord should be initialized"
         );
-        let page = PageSize::fit(layout)?;
+        let page = PageSize::fit(layout).ok_or(KError::Inval)?;
         self.allocate_pages(1, page).map(|r| r.into())
     }
 
@@ -237,12 +276,39 @@ unsafe impl addr::Allocator<UMASpace> for PhysicalMemoryManager {
 pub struct BootMemoryManager(RefCell<&'static mut MemblockSystem>);
 impl BootMemoryManager {
     pub fn managed_range(&self) -> AddrRange<UMASpace> { self.0.borrow().managed_range() }
+
+    /// Reserve `range` so that it is never allocated.
+    ///
+    /// See [`MemblockSystem::reserve_range`].
+    pub fn reserve_range(&self, range: AddrRange<UMASpace>) {
+        self.0.borrow_mut().reserve_range(range);
+    }
+
+    /// Reserve `size` bytes from the start of the lowest free block.
+    ///
+    /// Returns `None` if the lowest free block is too small. See
+    /// [`Self::reserve_range`].
+    pub fn reserve_lowest(&self, size: usize) -> Option<AddrRange<UMASpace>> {
+        let block = self.0.borrow().lowest_free()?;
+        let base = block.base.align_ceil(FRAME_SIZE)?;
+        let range = AddrRange::new(base, size);
+        if range.end() > block.end() {
+            return None;
+        }
+        self.reserve_range(range);
+        Some(range)
+    }
 }
 unsafe impl addr::Allocator<UMASpace> for BootMemoryManager {
-    fn allocate(&self, layout: Layout) -> Option<AddrRange<UMASpace>> {
-        let base = self.0.try_borrow_mut().ok()?.reserve(layout)?;
+    fn allocate(&self, layout: Layout) -> KResult<AddrRange<UMASpace>> {
+        let base = self
+            .0
+            .try_borrow_mut()
+            .map_err(|_| KError::Busy)?
+            .reserve(layout)
+            .ok_or(KError::NoMem)?;
         let size = layout.size();
-        Some(AddrRange { base, size })
+        Ok(AddrRange { base, size })
     }
 
     unsafe fn deallocate(&self, _addr: Addr<UMASpace>, _layout: Layout) {
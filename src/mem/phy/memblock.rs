@@ -283,11 +283,71 @@ impl MemblockSystem {
         }
     }
 
+    /// Mark `range` as reserved, carving it out of the free blocks.
+    ///
+    /// # Panics
+    /// Panics if memory was already reserved through [`Self::reserve`], or the
+    /// `MemblockSystem` is frozen.
+    pub fn reserve_range(&mut self, range: AddrRange<UMASpace>) {
+        assert!(!self.is_frozen);
+        assert!(
+            self.offset == 0,
+            "reserve_range should be called before any reservation"
+        );
+        if range.is_empty() {
+            return;
+        }
+
+        if let Some(partial_block) = self.partial_block.take() {
+            self.free_blocks.insert(partial_block);
+        }
+        self.carve_free(range);
+        self.partial_block = self.free_blocks.pop();
+    }
+
+    /// Mark defective `range` as reserved after [`Self::freeze`], carving it
+    /// out of the free blocks so it is never handed to the frame allocator.
+    pub fn reserve_defective(&mut self, range: AddrRange<UMASpace>) {
+        assert!(self.is_frozen);
+        if range.is_empty() {
+            return;
+        }
+        self.carve_free(range);
+    }
+
+    fn carve_free(&mut self, range: AddrRange<UMASpace>) {
+        let overlapped: ArrayVec<Memblock, MEMBLOCKS_LEN> = self
+            .free_blocks
+            .iter()
+            .filter(|block| block.base < range.end() && range.base < block.base + block.size)
+            .copied()
+            .collect();
+        for block in overlapped {
+            self.free_blocks.remove(block.base);
+            for rest in AddrRange::new(block.base, block.size).range_sub(range) {
+                if rest.is_empty() {
+                    continue;
+                }
+                self.free_blocks.insert(Memblock {
+                    base: rest.base,
+                    size: rest.size,
+                    typ: MemTyp::Free,
+                });
+            }
+        }
+        self.reserved_blocks.insert(Memblock {
+            base: range.base,
+            size: range.size,
+            typ: MemTyp::Reserved,
+        });
+    }
+
     /// Split the partial block into a free and a reserved block and insert
     /// into the respective [`Memblocks`]. The `MemblockSystem` should not
-    /// be modified after freeze.
+    /// be modified after freeze, except through [`Self::reserve_defective`].
     pub fn freeze(&mut self) {
         assert!(!self.is_frozen);
+        self.is_frozen = true;
         let Some(partial_block) = self.partial_block.take() else {
             return;
         };
@@ -313,6 +373,15 @@ impl MemblockSystem {
         });
     }
 
+    /// Returns the lowest free block, including the partial block.
+    pub fn lowest_free(&self) -> Option<AddrRange<UMASpace>> {
+        self.free_blocks
+            .iter()
+            .chain(self.partial_block.iter())
+            .min_by_key(|block| block.base)
+            .map(|block| AddrRange::new(block.base, block.size))
+    }
+
     pub fn managed_range(&self) -> AddrRange<UMASpace> { self.managed_range.clone() }
 
     pub fn free_blocks(&self) -> &Memblocks { &self.free_blocks }
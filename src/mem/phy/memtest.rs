@@ -0,0 +1,112 @@
+//! Boot-time memory test.
+//!
+//! With `memtest=N` on the command line, free memory is filled and verified
+//! with `N` patterns before the buddy allocator takes ownership of it, and
+//! failing frames are reserved so they are never allocated. A bare `memtest`,
+//! or one with an invalid `N`, runs every pattern.
+
+use core::fmt::Write as _;
+use core::ptr;
+
+use arrayvec::ArrayVec;
+
+use super::memblock::{Memblock, MemblockSystem};
+use super::FRAME_SIZE;
+use crate::boot::cmdline;
+use crate::drivers::vga::VGA_BUFFER;
+use crate::mem::addr::{Addr, AddrRange};
+use crate::mem::virt::PhysicalRemapSpace;
+use crate::mem::UMASpace;
+use crate::{klog, log};
+
+const PATTERNS: [u64; 4] = [
+    0x0000_0000_0000_0000,
+    0xFFFF_FFFF_FFFF_FFFF,
+    0xAAAA_AAAA_AAAA_AAAA,
+    0x5555_5555_5555_5555,
+];
+const FREE_BLOCKS_LEN: usize = 128;
+/// Bad frames each take a reserved memblock, so give up on memory that is
+/// too broken to track.
+const BAD_FRAMES_MAX: usize = 32;
+
+/// Test the free memory of a frozen `memblocks` if requested on the command
+/// line, reserving failing frames.
+///
+/// # Panics
+/// Panics if more than `BAD_FRAMES_MAX` frames fail.
+///
+/// # Safety
+/// PhysicalRemapSpace should be mapped, and free memory should not be in use.
+pub unsafe fn run(memblocks: &mut MemblockSystem) {
+    let Some(pass_cnt) = cmdline::get("memtest").map(|passes| match passes {
+        "" => PATTERNS.len(),
+        passes => match passes.parse::<usize>() {
+            Ok(pass_cnt) => pass_cnt.min(PATTERNS.len()),
+            Err(_) => {
+                klog!(
+                    Warn,
+                    "memtest: invalid memtest={}",
+                    passes
+                );
+                PATTERNS.len()
+            },
+        },
+    }) else {
+        return;
+    };
+
+    let mut bad_cnt = 0;
+    for &pattern in &PATTERNS[..pass_cnt] {
+        log!("memtest: pattern {:#018x}\n", pattern);
+        // Bad frames are carved out while testing, so iterate over a copy.
+        let free_blocks: ArrayVec<Memblock, FREE_BLOCKS_LEN> =
+            memblocks.free_blocks().into_iter().copied().collect();
+        for block in free_blocks {
+            let Some(base) = block.base.align_ceil(FRAME_SIZE) else {
+                continue;
+            };
+            let Some(end) = (block.base + block.size).align_floor(FRAME_SIZE) else {
+                continue;
+            };
+            let mut frame = base;
+            while frame < end {
+                // SAFETY: The frame is free and mapped through the remap.
+                if !unsafe { test_frame(frame, pattern) } {
+                    log!(
+                        "memtest: bad frame at {:#x}\n",
+                        frame.usize()
+                    );
+                    memblocks.reserve_defective(AddrRange::new(frame, FRAME_SIZE));
+                    bad_cnt += 1;
+                    assert!(
+                        bad_cnt <= BAD_FRAMES_MAX,
+                        "memtest: too many bad frames"
+                    );
+                }
+                frame = frame.byte_add(FRAME_SIZE);
+            }
+        }
+    }
+    log!(
+        "memtest: {} passes, {} bad frames reserved\n",
+        pass_cnt,
+        bad_cnt
+    );
+}
+
+/// Fill the frame at `paddr` with `pattern` and read it back.
+///
+/// # Safety
+/// The frame should be mapped through PhysicalRemapSpace and not in use.
+unsafe fn test_frame(paddr: Addr<UMASpace>, pattern: u64) -> bool {
+    let words = PhysicalRemapSpace::p2v(paddr).into_ptr::<u64>();
+    let word_cnt = FRAME_SIZE / size_of::<u64>();
+    // Volatile accesses keep the compiler from eliding the read back.
+    unsafe {
+        for idx in 0..word_cnt {
+            ptr::write_volatile(words.add(idx), pattern);
+        }
+        (0..word_cnt).all(|idx| ptr::read_volatile(words.add(idx)) == pattern)
+    }
+}
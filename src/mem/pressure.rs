@@ -0,0 +1,93 @@
+//! Memory pressure notification.
+//!
+//! Caches register a [`Shrinker`] to give memory back when free frames run
+//! low. Once an allocation leaves fewer free frames than the low watermark,
+//! shrinkers are called in priority order until the high watermark is
+//! restored. An allocation failing for lack of frames calls every shrinker
+//! before it is retried once.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use arrayvec::ArrayVec;
+
+use crate::interrupt::{self, InterruptGuard};
+use crate::stats::Stat;
+
+const SHRINKERS_LEN: usize = 8;
+
+static SHRINKERS: spin::Mutex<ArrayVec<&'static Shrinker, SHRINKERS_LEN>> =
+    spin::Mutex::new(ArrayVec::new_const());
+/// Whether shrinkers are running, so that allocations made by a shrinker do
+/// not recurse into reclaim.
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+
+static LOW_WATERMARK: AtomicUsize = AtomicUsize::new(0);
+static HIGH_WATERMARK: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) static RECLAIMED_FRAMES_STAT: Stat = Stat::counter("mem.reclaimed_frames");
+
+/// Reclaim callback of a cache.
+pub struct Shrinker {
+    pub name: &'static str,
+    /// Shrinkers with a lower priority are called first, so cheap to rebuild
+    /// caches should have a low priority.
+    pub priority: u8,
+    /// Free up to the given number of frames, returning the number freed.
+    ///
+    /// This may be called with the locks of any allocating code held, so it
+    /// should only try to take its locks.
+    pub reclaim: fn(usize) -> usize,
+}
+
+/// Register `shrinker` to be called under memory pressure.
+///
+/// # Panics
+/// Panics if the registry is full.
+pub fn register(shrinker: &'static Shrinker) {
+    let _guard = InterruptGuard::new();
+    let mut shrinkers = SHRINKERS.lock();
+    let idx = shrinkers.partition_point(|other| other.priority <= shrinker.priority);
+    shrinkers
+        .try_insert(idx, shrinker)
+        .expect("shrinker registry should not be full");
+}
+
+/// Set the watermarks from the free frames available at boot.
+pub(super) fn init(free_frames: usize) {
+    LOW_WATERMARK.store(free_frames / 32, Ordering::Relaxed);
+    HIGH_WATERMARK.store(free_frames / 16, Ordering::Relaxed);
+}
+
+/// Reclaim frames if `free_frames` is below the low watermark.
+pub(super) fn check(free_frames: usize) {
+    let high = HIGH_WATERMARK.load(Ordering::Relaxed);
+    if free_frames < LOW_WATERMARK.load(Ordering::Relaxed) {
+        reclaim(high - free_frames);
+    }
+}
+
+/// Call shrinkers in priority order until `frames` frames are freed.
+/// Returns the number of frames freed.
+///
+/// Nothing is reclaimed in interrupt context or from within a shrinker.
+pub fn reclaim(frames: usize) -> usize {
+    if interrupt::in_interrupt() || RECLAIMING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+
+    let shrinkers = {
+        let _guard = InterruptGuard::new();
+        SHRINKERS.lock().clone()
+    };
+    let mut freed = 0;
+    for shrinker in shrinkers {
+        if freed >= frames {
+            break;
+        }
+        freed += (shrinker.reclaim)(frames - freed);
+    }
+    RECLAIMED_FRAMES_STAT.add(freed as u64);
+
+    RECLAIMING.store(false, Ordering::Release);
+    freed
+}
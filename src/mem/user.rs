@@ -0,0 +1,221 @@
+//! Access to user memory from the kernel.
+//!
+//! Accessors check that the whole range lies in the user half, and recover
+//! from faults through the exception fixup table, so a bad user pointer is
+//! reported to the caller instead of faulting the kernel.
+//!
+//! Syscalls should wrap raw pointer arguments in [`UserPtr`] or [`UserSlice`]
+//! right away, which only allow access through these accessors.
+
+use core::arch::asm;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::{ptr, slice};
+
+use crate::cpu::features::{self, Feature};
+use crate::error::{KError, KResult};
+
+/// End of the lower canonical half, which holds user mappings.
+pub const USER_END: usize = 0x0000_8000_0000_0000;
+
+/// Returns whether `len` bytes from `addr` lie in the user half.
+pub fn is_user_range(addr: usize, len: usize) -> bool {
+    addr.checked_add(len).is_some_and(|end| end <= USER_END)
+}
+
+/// Copy `dst.len()` bytes from user address `src` into `dst`.
+///
+/// Fails with [`KError::Fault`] if the source range is not in the user half
+/// or not fully readable.
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> KResult<()> {
+    if !is_user_range(src, dst.len()) {
+        return Err(KError::Fault);
+    }
+    // SAFETY: dst is a valid buffer, and faults on src are fixed up.
+    let remaining = unsafe {
+        copy_fixup(
+            dst.as_mut_ptr(),
+            src as *const u8,
+            dst.len(),
+        )
+    };
+    if remaining != 0 {
+        return Err(KError::Fault);
+    }
+    Ok(())
+}
+
+/// Copy `src` to user address `dst`.
+///
+/// Fails with [`KError::Fault`] if the destination range is not in the user
+/// half or not fully writable.
+pub fn copy_to_user(dst: usize, src: &[u8]) -> KResult<()> {
+    if !is_user_range(dst, src.len()) {
+        return Err(KError::Fault);
+    }
+    // SAFETY: src is a valid buffer, and faults on dst are fixed up.
+    let remaining = unsafe { copy_fixup(dst as *mut u8, src.as_ptr(), src.len()) };
+    if remaining != 0 {
+        return Err(KError::Fault);
+    }
+    Ok(())
+}
+
+/// A type which can be copied from and to user memory as bytes.
+///
+/// # Safety
+/// Every bit pattern should be a valid value, and the type should have no
+/// padding.
+pub unsafe trait UserCopy: Copy {}
+macro_rules! impl_user_copy {
+    ($($ty:ty),*) => {
+        $(unsafe impl UserCopy for $ty {})*
+    };
+}
+impl_user_copy!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+unsafe impl<T: UserCopy, const N: usize> UserCopy for [T; N] {}
+
+/// A pointer to a `T` in user memory, checked to be in the user half and
+/// aligned.
+#[derive(Debug, Clone, Copy)]
+pub struct UserPtr<T: UserCopy> {
+    addr: usize,
+    _phantom: PhantomData<*mut T>,
+}
+impl<T: UserCopy> UserPtr<T> {
+    /// Wrap the raw user address `addr`.
+    ///
+    /// Fails with [`KError::Fault`] if the `T` at `addr` is not in the user
+    /// half, or [`KError::Inval`] if `addr` is misaligned for `T`.
+    pub fn new(addr: usize) -> KResult<Self> {
+        if !is_user_range(addr, size_of::<T>()) {
+            return Err(KError::Fault);
+        }
+        if addr % align_of::<T>() != 0 {
+            return Err(KError::Inval);
+        }
+        Ok(Self {
+            addr,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn addr(&self) -> usize { self.addr }
+
+    /// Copy the `T` in from user memory.
+    ///
+    /// Fails with [`KError::Fault`] if it is not fully readable.
+    pub fn read(&self) -> KResult<T> {
+        let mut val = MaybeUninit::<T>::uninit();
+        // SAFETY: The bytes of val are only written to.
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(
+                val.as_mut_ptr().cast::<u8>(),
+                size_of::<T>(),
+            )
+        };
+        copy_from_user(bytes, self.addr)?;
+        // SAFETY: Every bit pattern of a UserCopy type is valid.
+        Ok(unsafe { val.assume_init() })
+    }
+
+    /// Copy `val` out to user memory.
+    ///
+    /// Fails with [`KError::Fault`] if it is not fully writable.
+    pub fn write(&self, val: &T) -> KResult<()> {
+        // SAFETY: UserCopy types have no padding, so every byte of val is
+        // initialized.
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                ptr::from_ref(val).cast::<u8>(),
+                size_of::<T>(),
+            )
+        };
+        copy_to_user(self.addr, bytes)
+    }
+}
+
+/// A byte buffer in user memory, checked to be in the user half.
+#[derive(Debug, Clone, Copy)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+impl UserSlice {
+    /// Wrap the raw user buffer of `len` bytes at `addr`.
+    ///
+    /// Fails with [`KError::Fault`] if the buffer is not in the user half.
+    pub fn new(addr: usize, len: usize) -> KResult<Self> {
+        if !is_user_range(addr, len) {
+            return Err(KError::Fault);
+        }
+        Ok(Self { addr, len })
+    }
+
+    pub fn addr(&self) -> usize { self.addr }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Copy the start of the buffer into `dst`, returning the number of bytes
+    /// copied.
+    ///
+    /// Fails with [`KError::Fault`] if the copied range is not fully
+    /// readable.
+    pub fn read(&self, dst: &mut [u8]) -> KResult<usize> {
+        let len = dst.len().min(self.len);
+        copy_from_user(&mut dst[..len], self.addr)?;
+        Ok(len)
+    }
+
+    /// Copy `src` to the start of the buffer, returning the number of bytes
+    /// copied.
+    ///
+    /// Fails with [`KError::Fault`] if the copied range is not fully
+    /// writable.
+    pub fn write(&self, src: &[u8]) -> KResult<usize> {
+        let len = src.len().min(self.len);
+        copy_to_user(self.addr, &src[..len])?;
+        Ok(len)
+    }
+}
+
+/// Copy `len` bytes from `src` to `dst`, stopping at the first fault.
+///
+/// Returns the number of bytes left uncopied.
+///
+/// # Safety
+/// The ranges should not overlap, and a fault should only leave the copy
+/// incomplete.
+unsafe fn copy_fixup(dst: *mut u8, src: *const u8, len: usize) -> usize {
+    // SMAP faults on supervisor access to user pages unless the AC flag is
+    // set.
+    let is_smap = features::is_enabled(Feature::Smap);
+    if is_smap {
+        // SAFETY: AC is cleared right after the copy.
+        unsafe { asm!("stac", options(nostack)) };
+    }
+    let remaining;
+    // rep movsb leaves rcx at the count not yet copied when it faults.
+    unsafe {
+        asm!(
+            "2:",
+            "rep movsb",
+            "3:",
+            ".pushsection .ex_table, \"a\"",
+            ".balign 8",
+            ".quad 2b, 3b",
+            ".popsection",
+            inout("rcx") len => remaining,
+            inout("rdi") dst => _,
+            inout("rsi") src => _,
+            options(nostack, preserves_flags)
+        )
+    };
+    if is_smap {
+        // SAFETY: Restores AC cleared as on entry.
+        unsafe { asm!("clac", options(nostack)) };
+    }
+    remaining
+}
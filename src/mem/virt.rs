@@ -13,6 +13,7 @@ use core::sync::atomic::AtomicUsize;
 
 use super::addr::{Addr, AddrSpace, PageRange};
 use super::UMASpace;
+use crate::common::MiB;
 use crate::mem::phy;
 
 pub trait VirtSpace: AddrSpace {
@@ -71,3 +72,62 @@ impl VirtSpace for RecursivePagingSpace {
 impl AddrSpace for RecursivePagingSpace {
     const RANGE: Range<usize> = 0xFFFF_FE80_0000_0000..0xFFFF_FF00_0000_0000;
 }
+
+/// Check that the kernel virtual spaces are in the higher half, do not
+/// overlap, and are aligned to the paging structure they are mapped by.
+///
+/// # Panics
+/// Panics on the first violated assumption.
+pub(super) fn self_check() {
+    /// Size mapped by a PML4 entry.
+    const PML4E_SIZE: usize = 1 << 39;
+    const HIGHER_HALF_START: usize = 0xFFFF_8000_0000_0000;
+
+    let spaces: [(&str, Range<usize>, usize); 4] = [
+        (
+            "kernel image",
+            KernelImageSpace::RANGE,
+            2 * MiB,
+        ),
+        (
+            "physical remap",
+            PhysicalRemapSpace::RANGE,
+            PML4E_SIZE,
+        ),
+        (
+            "data stack",
+            DataStackSpace::RANGE,
+            PML4E_SIZE,
+        ),
+        (
+            "recursive paging",
+            RecursivePagingSpace::RANGE,
+            PML4E_SIZE,
+        ),
+    ];
+    for (idx, (name, range, align)) in spaces.iter().enumerate() {
+        assert!(
+            !range.is_empty() && range.start >= HIGHER_HALF_START,
+            "{} space should be a non-empty higher half range",
+            name
+        );
+        assert!(
+            range.start % align == 0,
+            "{} space should start aligned to {:#x}",
+            name,
+            align
+        );
+        for (other_name, other_range, _) in &spaces[idx + 1..] {
+            assert!(
+                range.end <= other_range.start || other_range.end <= range.start,
+                "{} space should not overlap {} space",
+                name,
+                other_name
+            );
+        }
+    }
+    assert!(
+        PhysicalRemapSpace::RANGE.len() >= UMASpace::RANGE.len(),
+        "physical remap space should cover UMASpace"
+    );
+}
@@ -0,0 +1,72 @@
+//! Boot initialization phases.
+//!
+//! `kmain` initializes subsystems in a fixed order. Each subsystem init
+//! [`enter`]s its phase, and APIs which depend on a subsystem [`require`] its
+//! phase, so that a misordered or repeated call panics right away instead of
+//! faulting later.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+static PHASE: AtomicU8 = AtomicU8::new(Phase::Early as u8);
+
+/// An initialization phase, in boot order. A phase is reached once its
+/// subsystem starts initializing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Phase {
+    /// Nothing is initialized.
+    Early,
+    /// The boot information is copied out.
+    Boot,
+    /// Paging and allocators.
+    Mem,
+    /// IDT and interrupt controller.
+    Interrupt,
+    Drivers,
+    /// Timer tick.
+    Time,
+}
+impl Phase {
+    const ALL: [Self; 6] = [
+        Self::Early,
+        Self::Boot,
+        Self::Mem,
+        Self::Interrupt,
+        Self::Drivers,
+        Self::Time,
+    ];
+}
+
+/// Returns the latest phase reached.
+pub fn current() -> Phase { Phase::ALL[PHASE.load(Ordering::Acquire) as usize] }
+
+/// Enter `phase`.
+///
+/// # Panics
+/// Panics if `phase` does not directly follow the current phase, which means
+/// its subsystem is initialized twice or out of order.
+#[track_caller]
+pub fn enter(phase: Phase) {
+    let prev = PHASE.fetch_max(phase as u8, Ordering::AcqRel);
+    assert!(
+        prev + 1 == phase as u8,
+        "init phase {:?} entered after {:?}",
+        phase,
+        Phase::ALL[prev as usize],
+    );
+}
+
+/// Check that `phase` is reached before using its subsystem.
+///
+/// # Panics
+/// Panics if `phase` is not reached yet.
+#[track_caller]
+pub fn require(phase: Phase) {
+    let cur = current();
+    assert!(
+        cur >= phase,
+        "{:?} is used before initialization, current phase is {:?}",
+        phase,
+        cur,
+    );
+}
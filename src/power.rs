@@ -0,0 +1,177 @@
+//! Platform reset, suspend and power off.
+
+use core::arch::asm;
+use core::ptr;
+
+use crate::acpi::{self, GenericAddress, SoftOff};
+use crate::common::hlt;
+use crate::common::pmio::{inw, outb, outl, outw, Port};
+use crate::drivers::vga::VGA_BUFFER;
+use crate::drivers::{self, ps2};
+use crate::error::KResult;
+use crate::flight::{self, EventKind};
+use crate::interrupt::{self, InterruptGuard};
+use crate::mem::addr::Addr;
+use crate::mem::PhysicalRemapSpace;
+use crate::{klog, time};
+
+const PCI_CONFIG_ADDRESS: Port = Port(0xCF8);
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+/// Power off ports of emulators without usable ACPI tables, with the value to
+/// write: QEMU, Bochs and older QEMU, then VirtualBox.
+const EMULATOR_POWER_OFF: &[(u16, u16)] = &[(0x604, 0x2000), (0xB004, 0x2000), (0x4004, 0x3400)];
+
+/// PM1 control bits.
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// Interrupt lines which wake the system from [`suspend`].
+const WAKE_IRQS: &[u8] = &[ps2::KEYBOARD_IRQ];
+
+/// Reboot the machine.
+///
+/// Tries the ACPI reset register, the keyboard controller reset line, then
+/// a triple fault, giving each method some time to take effect.
+pub fn reboot() -> ! {
+    flight::record(EventKind::Reboot, 0);
+    if let Some((reg, value)) = acpi::reset_register() {
+        acpi_reset(reg, value);
+        time::delay_ms(50);
+    }
+
+    ps2::pulse_reset();
+    time::delay_ms(50);
+
+    triple_fault()
+}
+
+/// Power off the machine.
+///
+/// Drivers are shut down in reverse registration order through their
+/// [`PmOps`][drivers::PmOps] before entering ACPI S5. If that does not take
+/// effect, the emulator power off ports are tried before halting.
+pub fn shutdown() -> ! {
+    flight::record(EventKind::Shutdown, 0);
+    klog!(Info, "power: shutting down");
+    drivers::shutdown();
+    VGA_BUFFER.lock().flush();
+
+    core::mem::forget(InterruptGuard::new());
+    if let Some(soft_off) = acpi::soft_off() {
+        acpi_soft_off(soft_off);
+        time::delay_ms(50);
+    }
+    for &(port, value) in EMULATOR_POWER_OFF {
+        outw(Port(port), value);
+    }
+    time::delay_ms(50);
+
+    klog!(
+        Error,
+        "power: power off failed, halting"
+    );
+    VGA_BUFFER.lock().flush();
+    hlt()
+}
+
+/// Suspend to idle until a wake interrupt.
+///
+/// Drivers are quiesced through their [`PmOps`][drivers::PmOps], every
+/// interrupt line but [`WAKE_IRQS`] is masked, and the CPU halts until a wake
+/// line is serviced. The tick is stopped while suspended.
+///
+/// Fails if a driver refuses to suspend or a wake line is not unmasked, in
+/// which case the system is left running.
+pub fn suspend() -> KResult<()> {
+    drivers::suspend()?;
+    if let Err(err) = interrupt::suspend_irqs(WAKE_IRQS) {
+        drivers::resume();
+        return Err(err);
+    }
+
+    loop {
+        let guard = InterruptGuard::new();
+        if interrupt::is_woken() {
+            break;
+        }
+        interrupt::wait_for_interrupt(guard);
+    }
+
+    interrupt::resume_irqs();
+    drivers::resume();
+    Ok(())
+}
+
+fn acpi_reset(reg: GenericAddress, value: u8) {
+    let addr = reg.address;
+    match reg.space_id {
+        GenericAddress::SPACE_MEMORY => {
+            let vaddr = PhysicalRemapSpace::p2v(Addr::new(addr as usize));
+            // SAFETY: The reset register is a valid MMIO register in the
+            // physical remap space.
+            unsafe { ptr::write_volatile(vaddr.into_ptr::<u8>(), value) };
+        },
+        GenericAddress::SPACE_IO => outb(Port(addr as u16), value),
+        GenericAddress::SPACE_PCI_CONFIG => {
+            // Device, function and offset on bus 0.
+            let dev = (addr >> 32) & 0xFFFF;
+            let func = (addr >> 16) & 0xFFFF;
+            let offset = addr & 0xFFFF;
+            let config_addr = 0x8000_0000 | (dev << 11) | (func << 8) | (offset & 0xFC);
+            outl(PCI_CONFIG_ADDRESS, config_addr as u32);
+            outb(
+                Port(PCI_CONFIG_DATA + (offset & 0b11) as u16),
+                value,
+            );
+        },
+        _ => (),
+    }
+}
+
+fn acpi_soft_off(soft_off: SoftOff) {
+    const ACPI_ENABLE_TIMEOUT_MS: u64 = 300;
+
+    if inw(Port(soft_off.pm1a_cnt)) & PM1_SCI_EN == 0 {
+        if let Some((smi_cmd, value)) = soft_off.acpi_enable {
+            outb(Port(smi_cmd), value);
+        }
+        for _ in 0..ACPI_ENABLE_TIMEOUT_MS {
+            if inw(Port(soft_off.pm1a_cnt)) & PM1_SCI_EN != 0 {
+                break;
+            }
+            time::delay_ms(1);
+        }
+    }
+
+    outw(
+        Port(soft_off.pm1a_cnt),
+        (soft_off.slp_typ_a as u16) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN,
+    );
+    if let Some(pm1b_cnt) = soft_off.pm1b_cnt {
+        outw(
+            Port(pm1b_cnt),
+            (soft_off.slp_typ_b as u16) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN,
+        );
+    }
+}
+
+fn triple_fault() -> ! {
+    #[repr(C, packed(2))]
+    struct NullIdtr {
+        limit: u16,
+        base: u64,
+    }
+
+    let idtr = NullIdtr { limit: 0, base: 0 };
+    // SAFETY: The machine is reset, so nothing runs afterwards.
+    unsafe {
+        asm!(
+            "lidt [{idtr}]",
+            "int3",
+            idtr = in(reg) &idtr,
+            options(noreturn)
+        )
+    }
+}
@@ -0,0 +1,86 @@
+//! Entropy pool stirred by interrupt timing.
+//!
+//! Every serviced IRQ mixes the TSC into the pool, so entropy keeps
+//! accumulating after boot even without a hardware RNG. Mixing is lock-free
+//! and only takes a few instructions, as it runs in the IRQ dispatch path.
+//!
+//! The output is not cryptographically strong.
+
+use core::arch::x86_64::{__cpuid, _rdrand64_step, _rdtsc};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::stats::{self, Stat};
+
+const POOL_LEN: usize = 4;
+const POOL_BITS: u64 = (POOL_LEN * u64::BITS as usize) as u64;
+/// Rounds of RDRAND mixed in at boot.
+const RDRAND_ROUNDS: usize = POOL_LEN;
+
+static POOL: [AtomicU64; POOL_LEN] = [const { AtomicU64::new(0) }; POOL_LEN];
+static MIX_IDX: AtomicUsize = AtomicUsize::new(0);
+static EXTRACT_CNT: AtomicU64 = AtomicU64::new(0);
+
+/// Estimated bits of entropy mixed in, crediting one bit per event.
+static ENTROPY_STAT: Stat = Stat::gauge("random.entropy_bits");
+
+/// Seed the pool from the TSC and RDRAND if available.
+pub fn init() {
+    stats::register(&ENTROPY_STAT);
+    // SAFETY: CPUID is checked to be supported during boot.
+    let has_rdrand = unsafe { __cpuid(1) }.ecx & (1 << 30) != 0;
+    if has_rdrand {
+        for _ in 0..RDRAND_ROUNDS {
+            let mut val = 0;
+            // SAFETY: RDRAND is supported.
+            if unsafe { _rdrand64_step(&mut val) } == 1 {
+                mix(val);
+                credit(u64::BITS as u64);
+            }
+        }
+    }
+    mix(tsc());
+}
+
+/// Mix the timing of an interrupt on `irq` into the pool.
+#[inline]
+pub fn add_irq_randomness(irq: u8) {
+    mix(tsc() ^ (irq as u64) << 56);
+    credit(1);
+}
+
+/// Returns a random number drawn from the pool.
+pub fn random_u64() -> u64 {
+    let cnt = EXTRACT_CNT.fetch_add(1, Ordering::Relaxed);
+    let mut acc = finalize(cnt ^ tsc());
+    for word in &POOL {
+        acc = finalize(acc ^ word.load(Ordering::Relaxed));
+    }
+    // Feed the output back, so that the pool changes between draws even
+    // without new events.
+    mix(acc);
+    acc
+}
+
+fn mix(val: u64) {
+    let idx = MIX_IDX.fetch_add(1, Ordering::Relaxed);
+    let mixed = finalize(val).rotate_left((idx * 17) as u32 % u64::BITS);
+    POOL[idx % POOL_LEN].fetch_xor(mixed, Ordering::Relaxed);
+}
+
+fn credit(bits: u64) {
+    if ENTROPY_STAT.get() < POOL_BITS {
+        ENTROPY_STAT.add(bits.min(POOL_BITS - ENTROPY_STAT.get()));
+    }
+}
+
+/// The splitmix64 finalizer.
+fn finalize(mut val: u64) -> u64 {
+    val = (val ^ (val >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    val = (val ^ (val >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    val ^ (val >> 31)
+}
+
+fn tsc() -> u64 {
+    // SAFETY: TSC is checked to be supported during boot.
+    unsafe { _rdtsc() }
+}
@@ -0,0 +1,28 @@
+//! Boot-time check of the layout assumptions made across modules.
+//!
+//! Some invariants, such as selectors matching the GDT built at runtime,
+//! cannot be checked at compile time. They are checked once here, so that a
+//! broken layout stops the boot with a clear message instead of showing up
+//! later as a triple fault or memory corruption.
+
+use crate::{gdt, interrupt, klog, mem};
+
+/// Run every check, panicking on the first violation.
+///
+/// This should be called after the GDT and IDT are loaded.
+pub fn run() {
+    let checks: [(&str, fn()); 3] = [
+        ("mem", mem::self_check),
+        ("gdt", gdt::self_check),
+        ("interrupt", interrupt::self_check),
+    ];
+    for (name, check) in checks {
+        check();
+        klog!(Debug, "self-check: {} passed", name);
+    }
+    klog!(
+        Info,
+        "self-check: {} checks passed",
+        checks.len()
+    );
+}
@@ -0,0 +1,146 @@
+//! Registry of named kernel statistics.
+//!
+//! Subsystems declare a `static` [`Stat`] and [`register`] it during their
+//! initialization. Consumers enumerate every registered stat through
+//! [`for_each`] instead of querying each subsystem.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use arrayvec::ArrayVec;
+
+use crate::interrupt::InterruptGuard;
+
+const STATS_LEN: usize = 64;
+
+static STATS: spin::Mutex<ArrayVec<&'static Stat, STATS_LEN>> =
+    spin::Mutex::new(ArrayVec::new_const());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatKind {
+    /// A monotonically increasing value.
+    Counter,
+    /// A value that may go up and down.
+    Gauge,
+}
+
+enum StatSrc {
+    Value(AtomicU64),
+    Fn(fn() -> u64),
+}
+
+/// A named statistic.
+///
+/// ```kexample
+/// static EVENTS: crate::stats::Stat = crate::stats::Stat::counter("example.events");
+///
+/// EVENTS.inc();
+/// assert!(EVENTS.get() == 1);
+/// ```
+pub struct Stat {
+    name: &'static str,
+    kind: StatKind,
+    src: StatSrc,
+}
+impl Stat {
+    /// Creates a counter starting at 0.
+    pub const fn counter(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: StatKind::Counter,
+            src: StatSrc::Value(AtomicU64::new(0)),
+        }
+    }
+
+    /// Creates a gauge starting at 0.
+    pub const fn gauge(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: StatKind::Gauge,
+            src: StatSrc::Value(AtomicU64::new(0)),
+        }
+    }
+
+    /// Creates a stat whose value is computed by `f` on every read.
+    pub const fn computed(name: &'static str, kind: StatKind, f: fn() -> u64) -> Self {
+        Self {
+            name,
+            kind,
+            src: StatSrc::Fn(f),
+        }
+    }
+
+    pub const fn name(&self) -> &'static str { self.name }
+
+    pub const fn kind(&self) -> StatKind { self.kind }
+
+    /// Returns the current value.
+    pub fn get(&self) -> u64 {
+        match &self.src {
+            StatSrc::Value(value) => value.load(Ordering::Relaxed),
+            StatSrc::Fn(f) => f(),
+        }
+    }
+
+    /// Increment the stat by 1.
+    pub fn inc(&self) { self.add(1) }
+
+    /// Increment the stat by `x`.
+    ///
+    /// # Panics
+    /// Panics if the stat is computed.
+    pub fn add(&self, x: u64) { self.value().fetch_add(x, Ordering::Relaxed); }
+
+    /// Decrement the gauge by `x`.
+    ///
+    /// # Panics
+    /// Panics if the stat is computed or not a gauge.
+    pub fn sub(&self, x: u64) {
+        debug_assert!(
+            self.kind == StatKind::Gauge,
+            "counter should not decrease"
+        );
+        self.value().fetch_sub(x, Ordering::Relaxed);
+    }
+
+    /// Set the gauge to `x`.
+    ///
+    /// # Panics
+    /// Panics if the stat is computed or not a gauge.
+    pub fn set(&self, x: u64) {
+        debug_assert!(
+            self.kind == StatKind::Gauge,
+            "counter should not be set"
+        );
+        self.value().store(x, Ordering::Relaxed);
+    }
+
+    fn value(&self) -> &AtomicU64 {
+        match &self.src {
+            StatSrc::Value(value) => value,
+            StatSrc::Fn(_) => panic!(
+                "computed stat {} cannot be modified",
+                self.name
+            ),
+        }
+    }
+}
+
+/// Register `stat` so that it is visible through [`for_each`].
+///
+/// # Panics
+/// Panics if the registry is full.
+pub fn register(stat: &'static Stat) {
+    let _guard = InterruptGuard::new();
+    STATS
+        .lock()
+        .try_push(stat)
+        .expect("stats registry should not be full");
+}
+
+/// Call `f` on every registered stat in registration order.
+pub fn for_each(mut f: impl FnMut(&Stat)) {
+    let _guard = InterruptGuard::new();
+    for stat in STATS.lock().iter() {
+        f(stat);
+    }
+}
@@ -0,0 +1,50 @@
+//! Identification of the running kernel.
+//!
+//! The values are embedded by the build script, so user programs and test
+//! scripts can check which build they are exercising.
+
+use core::fmt;
+
+static UNAME: Uname = Uname {
+    sysname: "koe-os",
+    release: env!("CARGO_PKG_VERSION"),
+    version: env!("KOE_GIT_HASH"),
+    build_time: parse_u64(env!("KOE_BUILD_TIME")),
+    features: env!("KOE_FEATURES"),
+};
+
+/// Build identification of the kernel, as in `uname`.
+#[derive(Debug)]
+pub struct Uname {
+    pub sysname: &'static str,
+    /// Crate version.
+    pub release: &'static str,
+    /// Short git hash, suffixed with `-dirty` for uncommitted changes.
+    pub version: &'static str,
+    /// Unix time of the build.
+    pub build_time: u64,
+    /// Comma separated Cargo features the kernel is built with.
+    pub features: &'static str,
+}
+impl fmt::Display for Uname {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({}) built at {} [{}]",
+            self.sysname, self.release, self.version, self.build_time, self.features
+        )
+    }
+}
+
+pub fn uname() -> &'static Uname { &UNAME }
+
+const fn parse_u64(s: &str) -> u64 {
+    let bytes = s.as_bytes();
+    let mut val = 0;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        val = val * 10 + (bytes[idx] - b'0') as u64;
+        idx += 1;
+    }
+    val
+}
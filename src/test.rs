@@ -1,4 +1,37 @@
+use alloc::alloc::Allocator;
 use alloc::vec::Vec;
+use core::alloc::Layout;
+#[cfg(feature = "examples")]
+use core::fmt::Write as _;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::KError;
+use crate::executor::{self, WaitQueue};
+use crate::flight::{self, EventKind, Recorder};
+use crate::mem::addr::Addr;
+use crate::mem::dma::{self, DmaDir, DmaMask};
+use crate::mem::user::{UserPtr, UserSlice};
+use crate::mem::{
+    self, pressure, user, GlobalAllocator, PageAllocator, PhysicalRemapSpace, SlabAllocator,
+};
+#[cfg(feature = "examples")]
+use crate::{drivers::vga::VGA_BUFFER, log};
+use crate::{fault, time};
+
+#[cfg(feature = "examples")]
+include!(concat!(env!("OUT_DIR"), "/examples.rs"));
+
+/// Run the tests needing only memory to be initialized, in order.
+pub fn run_mem_tests() {
+    test_mem();
+    test_alloc_fault();
+    test_dma();
+    test_dma_bounce();
+    test_flight();
+    test_slab_cap();
+    test_pressure();
+}
 
 pub fn test_mem() {
     // FIXME: reorganize test cases
@@ -37,3 +70,199 @@ pub fn test_mem() {
         }
     }
 }
+
+pub fn test_user_access() {
+    let mut buf = [0u8; 8];
+    // The user half is not mapped in the kernel memory map.
+    assert!(user::copy_from_user(&mut buf, 0x1000) == Err(KError::Fault));
+    assert!(user::copy_to_user(0x1000, &buf) == Err(KError::Fault));
+    // The range crosses into the kernel half.
+    assert!(user::copy_from_user(&mut buf, user::USER_END - 4) == Err(KError::Fault));
+
+    assert!(UserPtr::<u64>::new(0x1001).err() == Some(KError::Inval));
+    assert!(UserPtr::<u64>::new(user::USER_END - 4).err() == Some(KError::Fault));
+    let ptr = UserPtr::<u64>::new(0x1000).expect("pointer should be in the user half");
+    assert!(ptr.read() == Err(KError::Fault));
+    assert!(ptr.write(&0) == Err(KError::Fault));
+    let slice = UserSlice::new(0x1000, 16).expect("slice should be in the user half");
+    assert!(slice.read(&mut buf) == Err(KError::Fault));
+    assert!(UserSlice::new(user::USER_END - 4, 8).err() == Some(KError::Fault));
+}
+
+pub fn test_dma() {
+    let mut buf = Vec::from([0xA5u8; 64]);
+    let vaddr = buf.as_ptr() as usize;
+    let ptr = NonNull::from(buf.as_mut_slice());
+    // SAFETY: buf is not accessed until the mapping is dropped.
+    let mapping = unsafe { dma::dma_map_single(ptr, DmaMask::BITS_64, DmaDir::ToDevice) }
+        .expect("heap buffer should be mappable");
+    assert!(!mapping.is_bounced());
+    assert!(
+        Some(mapping.dev_addr() as usize) == mem::translate_kernel(vaddr).map(|addr| addr.usize())
+    );
+    dma::dma_unmap(mapping);
+    assert!(buf.iter().all(|byte| *byte == 0xA5));
+}
+
+pub fn test_dma_bounce() {
+    // Frames of the page allocator lie above the bounce pool, so a mask
+    // ending below the buffer forces bouncing through the pool.
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+    let page = PageAllocator
+        .allocate(layout)
+        .expect("page should be allocatable");
+    let vaddr = page.cast::<u8>().as_ptr() as usize;
+    let pbase = mem::translate_kernel(vaddr).expect("page should be mapped");
+    let mask = DmaMask::limit(pbase.usize() as u64 - 1);
+    let buf = NonNull::slice_from_raw_parts(page.cast::<u8>(), 64);
+    // SAFETY: page is valid and the slice is within it.
+    unsafe { buf.cast::<u8>().write_bytes(0xA5, buf.len()) };
+
+    // SAFETY: buf is not accessed until the mapping is dropped.
+    let mapping = unsafe { dma::dma_map_single(buf, mask, DmaDir::ToDevice) }
+        .expect("bounce pool should be reachable");
+    assert!(mapping.is_bounced());
+    assert!(mapping.dev_addr() < pbase.usize() as u64);
+    let bounce = PhysicalRemapSpace::p2v(Addr::new(mapping.dev_addr() as usize)).into_ptr::<u8>();
+    // SAFETY: The bounce frames hold the buffer length.
+    assert!(
+        unsafe { core::slice::from_raw_parts(bounce, buf.len()) }
+            .iter()
+            .all(|byte| *byte == 0xA5)
+    );
+    dma::dma_unmap(mapping);
+
+    // SAFETY: buf is not accessed until the mapping is dropped.
+    let mapping = unsafe { dma::dma_map_single(buf, mask, DmaDir::FromDevice) }
+        .expect("bounce pool should be reachable");
+    assert!(mapping.is_bounced());
+    let bounce = PhysicalRemapSpace::p2v(Addr::new(mapping.dev_addr() as usize)).into_ptr::<u8>();
+    // SAFETY: The bounce frames hold the buffer length.
+    unsafe { bounce.write_bytes(0x5A, buf.len()) };
+    dma::dma_unmap(mapping);
+    // SAFETY: The mapping is dropped and buf is valid.
+    assert!(unsafe { buf.as_ref() }.iter().all(|byte| *byte == 0x5A));
+
+    // SAFETY: page is allocated above with layout.
+    unsafe { PageAllocator.deallocate(page.cast(), layout) };
+}
+
+pub fn test_alloc_fault() {
+    // Injected failures should not be recorded as real ones.
+    flight::set_suppressed(true);
+    let site = fault::find("mem.alloc").expect("mem.alloc should be registered");
+    site.set_interval(2);
+    let mut buf: Vec<u8> = Vec::new();
+    let first = buf.try_reserve(16);
+    let mut buf: Vec<u8> = Vec::new();
+    let second = buf.try_reserve(16);
+    site.set_interval(0);
+
+    let site = fault::find("mem.alloc_pages").expect("mem.alloc_pages should be registered");
+    site.set_interval(1);
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+    let res = PageAllocator.allocate(layout);
+    site.set_interval(0);
+    flight::set_suppressed(false);
+
+    assert!(first.is_ok());
+    assert!(second.is_err());
+    assert!(res.is_err());
+}
+
+pub fn test_slab_cap() {
+    const ALLOCS_LEN: usize = 64;
+
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    SlabAllocator::set_cap(layout.size(), Some(0));
+    SlabAllocator::set_fail_over_cap(true);
+    let mut allocs = Vec::with_capacity(ALLOCS_LEN);
+    let mut is_capped = false;
+    for _ in 0..ALLOCS_LEN {
+        match GlobalAllocator.allocate(layout) {
+            Ok(ptr) => allocs.push(ptr),
+            Err(_) => {
+                is_capped = true;
+                break;
+            },
+        }
+    }
+    assert!(is_capped);
+
+    SlabAllocator::set_fail_over_cap(false);
+    let over_cap = GlobalAllocator
+        .allocate(layout)
+        .expect("allocation over the cap should fall back to pages");
+    assert!(over_cap.cast::<u8>().as_ptr() as usize % 4096 == 0);
+    allocs.push(over_cap);
+
+    for ptr in allocs {
+        // SAFETY: `ptr` is allocated above with `layout`.
+        unsafe { GlobalAllocator.deallocate(ptr.cast(), layout) };
+    }
+    SlabAllocator::set_cap(layout.size(), None);
+}
+
+pub fn test_pressure() {
+    const ALLOCS_LEN: usize = 16;
+
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let allocs: Vec<_> = (0..ALLOCS_LEN)
+        .map(|_| {
+            GlobalAllocator
+                .allocate(layout)
+                .expect("memory should be available")
+        })
+        .collect();
+    for ptr in allocs {
+        // SAFETY: `ptr` is allocated above with `layout`.
+        unsafe { GlobalAllocator.deallocate(ptr.cast(), layout) };
+    }
+    assert!(pressure::reclaim(usize::MAX) > 0);
+}
+
+pub fn test_executor() {
+    static QUEUE: WaitQueue = WaitQueue::new();
+    static STEP: AtomicUsize = AtomicUsize::new(0);
+
+    executor::spawn(async {
+        QUEUE
+            .wait_until(|| (STEP.load(Ordering::Relaxed) == 1).then_some(()))
+            .await;
+        executor::sleep_ms(10).await;
+        STEP.store(2, Ordering::Relaxed);
+    })
+    .expect("task table should not be full");
+
+    executor::run_ready();
+    assert_eq!(STEP.load(Ordering::Relaxed), 0);
+    STEP.store(1, Ordering::Relaxed);
+    QUEUE.wake_all();
+    time::wait_until(|| (STEP.load(Ordering::Relaxed) == 2).then_some(()));
+}
+
+pub fn test_flight() {
+    const ARG: u64 = 0xF11E;
+
+    // Record into a scratch ring, since the kernel ring is kept across warm
+    // resets.
+    let recorder = Recorder::new();
+    flight::record_in(&recorder, EventKind::AllocFail, ARG);
+    let mut events = Vec::new();
+    flight::for_each_in(&recorder, |event| events.push(*event));
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, EventKind::AllocFail);
+    assert_eq!(events[0].arg, ARG);
+}
+
+/// Run the doc examples extracted by the build script.
+///
+/// This should be called once the kernel is initialized, since examples may
+/// use any subsystem.
+#[cfg(feature = "examples")]
+pub fn test_examples() {
+    for (location, example) in EXAMPLES {
+        log!("example {}\n", location);
+        example();
+    }
+}
@@ -0,0 +1,307 @@
+//! Kernel timekeeping driven by the periodic timer tick.
+//!
+//! The tick frequency is taken from the `hz=` command line option. Every tick
+//! advances [`jiffies`], and expires software timers registered through
+//! [`add_timer`].
+//!
+//! Each tick is also accounted as idle or busy time of the CPU it lands on,
+//! which is reported through [`cpu_load`].
+//!
+//! Short busy-waits are provided by [`delay_us`] and [`delay_ms`], which use
+//! the TSC once calibrated and PIT channel 2 otherwise.
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use arrayvec::ArrayVec;
+
+use crate::boot::cmdline;
+use crate::common::lock::{self, Mutex};
+use crate::cpu::{self, CpuId, MAX_CPUS};
+use crate::drivers::pit;
+use crate::error::{KError, KResult};
+use crate::executor;
+use crate::interrupt::{self, InterruptGuard, IrqReturn};
+use crate::phase::{self, Phase};
+use crate::stats::{self, Stat, StatKind};
+
+/// Tick frequency used when `hz=` is not given.
+pub const DEFAULT_TICK_HZ: u32 = 100;
+/// Accepted range of `hz=`.
+const TICK_HZ_RANGE: core::ops::RangeInclusive<u32> = 19..=10_000;
+
+const TIMERS_LEN: usize = 32;
+
+static JIFFIES: AtomicU64 = AtomicU64::new(0);
+static TICK_HZ: AtomicU32 = AtomicU32::new(DEFAULT_TICK_HZ);
+static CPU_TIMES: [CpuTime; MAX_CPUS] = [const { CpuTime::new() }; MAX_CPUS];
+/// CPU receiving the tick. The PIT is routed to the CPU calling [`init`]
+/// through the PIC, so its id is cached instead of running CPUID, which
+/// exits to the hypervisor, on every tick.
+static TICK_CPU: AtomicUsize = AtomicUsize::new(0);
+/// TSC cycles per millisecond, or 0 if the TSC is not usable.
+static TSC_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+static TIMERS: Mutex<ArrayVec<Timer, TIMERS_LEN>> =
+    Mutex::new("time.timers", ArrayVec::new_const());
+static PENDING_TIMER_CNT: AtomicUsize = AtomicUsize::new(0);
+
+static JIFFIES_STAT: Stat = Stat::computed(
+    "time.jiffies",
+    StatKind::Counter,
+    jiffies,
+);
+static UPTIME_STAT: Stat = Stat::computed(
+    "time.uptime_ms",
+    StatKind::Counter,
+    uptime_ms,
+);
+
+/// Length of the window [`cpu_load`] is measured over.
+pub const LOAD_WINDOW_MS: u64 = 1000;
+
+/// Idle and busy time of a CPU in ticks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuLoad {
+    pub idle: u64,
+    pub busy: u64,
+}
+impl CpuLoad {
+    /// Returns the busy share in percent.
+    pub fn busy_percent(&self) -> u64 {
+        let total = self.idle + self.busy;
+        if total == 0 {
+            0
+        } else {
+            self.busy * 100 / total
+        }
+    }
+}
+
+struct CpuTime {
+    is_idle: AtomicBool,
+    idle: AtomicU64,
+    busy: AtomicU64,
+    /// Ticks at the start of the current window.
+    window_start: spin::Mutex<CpuLoad>,
+    /// Ticks within the last complete window.
+    last_window: spin::Mutex<CpuLoad>,
+}
+impl CpuTime {
+    const fn new() -> Self {
+        Self {
+            is_idle: AtomicBool::new(false),
+            idle: AtomicU64::new(0),
+            busy: AtomicU64::new(0),
+            window_start: spin::Mutex::new(CpuLoad { idle: 0, busy: 0 }),
+            last_window: spin::Mutex::new(CpuLoad { idle: 0, busy: 0 }),
+        }
+    }
+
+    fn total(&self) -> CpuLoad {
+        CpuLoad {
+            idle: self.idle.load(Ordering::Relaxed),
+            busy: self.busy.load(Ordering::Relaxed),
+        }
+    }
+
+    fn account_tick(&self, now: u64) {
+        if self.is_idle.load(Ordering::Relaxed) {
+            self.idle.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.busy.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if now % ms_to_jiffies(LOAD_WINDOW_MS) == 0 {
+            let total = self.total();
+            let mut start = self.window_start.lock();
+            *self.last_window.lock() = CpuLoad {
+                idle: total.idle - start.idle,
+                busy: total.busy - start.busy,
+            };
+            *start = total;
+        }
+    }
+}
+
+/// A one-shot software timer.
+#[derive(Debug, Clone, Copy)]
+struct Timer {
+    expires: u64,
+    callback: fn(),
+}
+
+/// Start the periodic timer tick.
+pub fn init() {
+    phase::enter(Phase::Time);
+    let hz = cmdline::parse::<u32>("hz")
+        .filter(|hz| TICK_HZ_RANGE.contains(hz))
+        .unwrap_or(DEFAULT_TICK_HZ);
+
+    TICK_CPU.store(cpu::current_id(), Ordering::Relaxed);
+    calibrate_tsc();
+    let hz = pit::init(hz);
+    TICK_HZ.store(hz, Ordering::Relaxed);
+    interrupt::register_irq(pit::IRQ, tick_handler)
+        .expect("timer irq line should have a free handler slot");
+    interrupt::unmask_irq(pit::IRQ).expect("timer irq should be a valid line");
+
+    lock::register(&TIMERS);
+    stats::register(&JIFFIES_STAT);
+    stats::register(&UPTIME_STAT);
+}
+
+/// Returns the number of ticks since [`init`].
+pub fn jiffies() -> u64 { JIFFIES.load(Ordering::Relaxed) }
+
+/// Returns the milliseconds elapsed since [`init`].
+pub fn uptime_ms() -> u64 { jiffies_to_ms(jiffies()) }
+
+/// Returns the tick frequency in Hz.
+pub fn tick_hz() -> u32 { TICK_HZ.load(Ordering::Relaxed) }
+
+/// Convert milliseconds to ticks, rounding up.
+pub fn ms_to_jiffies(ms: u64) -> u64 { (ms * tick_hz() as u64).div_ceil(1000) }
+
+/// Convert ticks to milliseconds, rounding down.
+pub fn jiffies_to_ms(jiffies: u64) -> u64 { jiffies * 1000 / tick_hz() as u64 }
+
+/// Call `callback` in interrupt context once [`jiffies`] reaches `expires`.
+///
+/// Fails with [`KError::NoSpc`] if the timer table is full.
+///
+/// ```kexample
+/// fn on_expire() {}
+///
+/// let expires = crate::time::jiffies() + crate::time::ms_to_jiffies(10);
+/// crate::time::add_timer(expires, on_expire).expect("timer table should not be full");
+/// ```
+pub fn add_timer(expires: u64, callback: fn()) -> KResult<()> {
+    phase::require(Phase::Time);
+    let _guard = InterruptGuard::new();
+    TIMERS
+        .lock()
+        .try_push(Timer { expires, callback })
+        .map_err(|_| KError::NoSpc)?;
+    PENDING_TIMER_CNT.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Busy-wait for at least `us` microseconds.
+///
+/// This may be called before [`init`] and with interrupt disabled.
+pub fn delay_us(us: u64) {
+    let tsc_per_ms = TSC_PER_MS.load(Ordering::Relaxed);
+    if tsc_per_ms != 0 {
+        // SAFETY: TSC is checked to be invariant during calibration.
+        let end = unsafe { _rdtsc() } + (us * tsc_per_ms).div_ceil(1000);
+        while unsafe { _rdtsc() } < end {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+
+    let mut cycles = (us * pit::BASE_FREQUENCY as u64).div_ceil(1_000_000);
+    while cycles > 0 {
+        let cnt = cycles.min(u16::MAX as u64);
+        pit::wait_cycles(cnt as u16);
+        cycles -= cnt;
+    }
+}
+
+/// Busy-wait for at least `ms` milliseconds.
+///
+/// This may be called before [`init`] and with interrupt disabled.
+pub fn delay_ms(ms: u64) { delay_us(ms * 1000) }
+
+/// Measure the TSC frequency against the PIT, if the TSC is invariant.
+fn calibrate_tsc() {
+    const CALIBRATE_MS: u64 = 10;
+    const CPUID_POWER_MANAGEMENT: u32 = 0x8000_0007;
+    const INVARIANT_TSC: u32 = 1 << 8;
+
+    // SAFETY: CPUID is checked to be supported during boot.
+    let max_ext_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    if max_ext_leaf < CPUID_POWER_MANAGEMENT
+        || unsafe { __cpuid(CPUID_POWER_MANAGEMENT) }.edx & INVARIANT_TSC == 0
+    {
+        return;
+    }
+
+    let cycles = pit::BASE_FREQUENCY as u64 * CALIBRATE_MS / 1000;
+    let _guard = InterruptGuard::new();
+    // SAFETY: TSC is supported on all x86-64 CPUs.
+    let start = unsafe { _rdtsc() };
+    pit::wait_cycles(cycles as u16);
+    let end = unsafe { _rdtsc() };
+    TSC_PER_MS.store(
+        (end - start) / CALIBRATE_MS,
+        Ordering::Relaxed,
+    );
+}
+
+/// Returns the load of `cpu` over the last [`LOAD_WINDOW_MS`], or `None` if
+/// `cpu` is not a valid id.
+pub fn cpu_load(cpu: CpuId) -> Option<CpuLoad> {
+    let _guard = InterruptGuard::new();
+    Some(*CPU_TIMES.get(cpu)?.last_window.lock())
+}
+
+/// Returns the load of `cpu` since [`init`], or `None` if `cpu` is not a
+/// valid id.
+pub fn cpu_time(cpu: CpuId) -> Option<CpuLoad> { Some(CPU_TIMES.get(cpu)?.total()) }
+
+/// Idle the CPU until `poll` returns `Some`, waking up only to service
+/// interrupts and run woken [`executor`] tasks.
+///
+/// `poll` is called with interrupt disabled, and should be made ready by an
+/// interrupt handler or a task.
+pub fn wait_until<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+    loop {
+        executor::run_ready();
+        let guard = InterruptGuard::new();
+        if let Some(val) = poll() {
+            return val;
+        }
+        if executor::has_ready() {
+            continue;
+        }
+        let is_idle = &CPU_TIMES[cpu::current_id()].is_idle;
+        is_idle.store(true, Ordering::Relaxed);
+        interrupt::wait_for_interrupt(guard);
+        is_idle.store(false, Ordering::Relaxed);
+    }
+}
+
+fn tick_handler() -> IrqReturn {
+    let now = JIFFIES.fetch_add(1, Ordering::Relaxed) + 1;
+    let cpu_time = &CPU_TIMES[TICK_CPU.load(Ordering::Relaxed)];
+    cpu_time.account_tick(now);
+
+    // Nothing can be waiting on the tick.
+    if cpu_time.is_idle.load(Ordering::Relaxed) && PENDING_TIMER_CNT.load(Ordering::Relaxed) == 0 {
+        return IrqReturn::Handled;
+    }
+
+    run_timers(now);
+    IrqReturn::Handled
+}
+
+fn run_timers(now: u64) {
+    let mut expired: ArrayVec<Timer, TIMERS_LEN> = ArrayVec::new();
+    let mut timers = TIMERS.lock();
+    timers.retain(|timer| {
+        let is_expired = timer.expires <= now;
+        if is_expired {
+            expired.push(*timer);
+        }
+        !is_expired
+    });
+    PENDING_TIMER_CNT.store(timers.len(), Ordering::Relaxed);
+    // Callbacks may add timers.
+    drop(timers);
+
+    for timer in expired {
+        (timer.callback)();
+    }
+}